@@ -1,7 +1,7 @@
 use bonk::{bonk, Bonk};
 use itertools::iproduct;
 
-use criterion::{criterion_group, criterion_main, Criterion};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
 
 fn iproduct_macro() {
     static A: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ";
@@ -40,7 +40,7 @@ impl Bonk for S {
     }
     fn check(&mut self, buf: &[u8]) -> bool {
         if &buf == b"SKY-ZZZZ-9999" {
-            println!("{}", unsafe { std::str::from_utf8_unchecked(&buf) });
+            println!("{}", unsafe { std::str::from_utf8_unchecked(buf) });
             true
         } else {
             false
@@ -52,17 +52,99 @@ fn bonk_macro() {
     bonk!(r"SKY-\A{4}-\d{4}", S, true, true);
 }
 
+/// Stops at the last candidate, so that every candidate is tried
+struct Last;
+
+impl Bonk for Last {
+    fn new(_id: usize) -> Self {
+        Self
+    }
+    fn check(&mut self, buf: &[u8]) -> bool {
+        black_box(buf) == b"SKY-ZZZ-999"
+    }
+}
+
+fn bonk_unrolled() {
+    bonk!(r"SKY-\A{3}-\d{3}", Last, false, false, backend = unrolled);
+}
+
+fn bonk_odometer() {
+    bonk!(r"SKY-\A{3}-\d{3}", Last, false, false, backend = odometer);
+}
+
+struct Word;
+
+impl Bonk for Word {
+    fn new(_id: usize) -> Self {
+        Self
+    }
+    fn check(&mut self, buf: &[u8]) -> bool {
+        black_box(buf) == b"bonk"
+    }
+}
+
+// \w{1,12} unrolls into 78 loops, well under ODOMETER_THRESHOLD (2048)
+fn words_unrolled() {
+    bonk!(
+        r"\w{1,12}",
+        Word,
+        false,
+        false,
+        shortest_first = true,
+        backend = unrolled
+    );
+}
+
+fn words_odometer() {
+    bonk!(
+        r"\w{1,12}",
+        Word,
+        false,
+        false,
+        shortest_first = true,
+        backend = odometer
+    );
+}
+
+// \w{1,64} unrolls into 2080 loops, just over ODOMETER_THRESHOLD, so it
+// picks the odometer unless it's told otherwise
+fn long_words_auto() {
+    bonk!(r"\w{1,64}", Word, false, false, shortest_first = true);
+}
+
+fn long_words_unrolled() {
+    bonk!(
+        r"\w{1,64}",
+        Word,
+        false,
+        false,
+        shortest_first = true,
+        backend = unrolled
+    );
+}
+
 fn bench_macros(c: &mut Criterion) {
     let mut group = c.benchmark_group("macros");
-    group.bench_function("iproduct", |b| b.iter(|| iproduct_macro()));
-    group.bench_function("bonk", |b| b.iter(|| bonk_macro()));
+    group.bench_function("iproduct", |b| b.iter(iproduct_macro));
+    group.bench_function("bonk", |b| b.iter(bonk_macro));
+    group.finish();
+}
+
+fn bench_backends(c: &mut Criterion) {
+    let mut group = c.benchmark_group("backends");
+    group.bench_function("unrolled", |b| b.iter(bonk_unrolled));
+    group.bench_function("odometer", |b| b.iter(bonk_odometer));
+    group.bench_function("words unrolled", |b| b.iter(words_unrolled));
+    group.bench_function("words odometer", |b| b.iter(words_odometer));
+    group.bench_function("long words auto", |b| b.iter(long_words_auto));
+    group.bench_function("long words unrolled", |b| b.iter(long_words_unrolled));
     group.finish();
 }
 
 criterion_group!(
     name = benches;
     config = Criterion::default().sample_size(10);
-    targets = bench_macros
+    targets = bench_macros, bench_backends
 );
 
 criterion_main!(benches);
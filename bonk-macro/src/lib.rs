@@ -1,143 +1,1131 @@
-#![feature(proc_macro_span)]
 extern crate proc_macro;
 
-mod lexer;
-mod parser;
-
-use lexer::{Lexer, ParseErr, Token};
-use parser::{Change, Final, Init, Partition, Run};
+use bonk_pattern::{
+    Change, Compliance, Final, Growth, Init, Lexer, Limits, Mask, Options, ParseErr, Partition,
+    Policy, Rule, Run, Values, Write,
+};
 use proc_macro::TokenStream;
-use proc_macro2::{Literal, Span};
-use quote::{format_ident, quote};
+use proc_macro2::{Literal, Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote, quote_spanned};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
 use syn::parse::{self, Parse, ParseStream};
-use syn::{parse_macro_input, Error, Ident, LitBool, LitStr, Token};
+use syn::punctuated::Punctuated;
+use syn::{braced, parse_macro_input, Error, Ident, Lit, LitBool, LitInt, LitStr, Token};
 
 struct Config {
     handler: Ident,
     result: Final,
     abort: bool,
     threaded: bool,
+    /// Where each warning points to and its message
+    warnings: Vec<(Span, String)>,
+    /// Whether the runs are enumerated by the odometer instead of loops
+    odometer: bool,
+}
+
+/// The most loops that the macro unrolls before it switches to the odometer,
+/// counted before the runs are split across threads; unrolled loops run about
+/// 9x faster, but a release build of `\w{1,k}` takes 8s at 1035 loops and 37s
+/// at 2080, so this is about where building them stops being worth it
+const ODOMETER_THRESHOLD: usize = 2048;
+
+/// The language that the candidates are described in
+enum Source {
+    Pattern,
+    Mask,
 }
 
-fn make_span(src: &str, idx: usize, old_span: Span) -> Span {
-    let mut literal = Literal::string(src);
-    literal.set_span(old_span);
-    literal.subspan(idx + 1..idx + 2).unwrap_or(old_span)
+/// Returns the span of the byte at `idx` in a literal's value, or the whole
+/// literal if the compiler can't point inside of it; the literal's source
+/// can have a prefix and escapes, so they're stepped over to find the byte
+fn make_span(literal: &Literal, idx: usize) -> Span {
+    let src = literal.to_string();
+    let raw = src.starts_with('r') || src.starts_with("br");
+    let end = src.rfind('"').unwrap_or(0);
+    let mut at = src.find('"').map_or(end, |i| i + 1);
+    let mut value = 0;
+    // past the end of the value, the span is the closing quote
+    let mut len = 1;
+    while at < end {
+        let rest = &src[at..];
+        let written;
+        (len, written) = match rest.chars().next() {
+            Some('\\') if !raw => escape(rest),
+            Some(c) => (c.len_utf8(), c.len_utf8()),
+            None => break,
+        };
+        if written > 0 && value + written > idx {
+            break;
+        }
+        value += written;
+        at += len;
+        len = 1;
+    }
+    literal
+        .subspan(at..at + len)
+        .unwrap_or_else(|| literal.span())
+}
+
+/// Returns how many bytes of a literal's source the escape at the start of
+/// `rest` takes up, and how many bytes it writes to the literal's value
+fn escape(rest: &str) -> (usize, usize) {
+    match rest.as_bytes().get(1) {
+        Some(b'x') => (4, 1),
+        Some(b'u') => {
+            let close = rest.find('}').map_or(rest.len(), |i| i + 1);
+            let c = rest
+                .get(3..close - 1)
+                .and_then(|hex| u32::from_str_radix(&hex.replace('_', ""), 16).ok())
+                .and_then(char::from_u32);
+            (close, c.map_or(1, char::len_utf8))
+        }
+        // a backslash at the end of a line skips the whitespace after it
+        Some(b'\n') | Some(b'\r') => {
+            let skipped = rest[1..]
+                .find(|c: char| !c.is_whitespace())
+                .map_or(rest.len(), |i| i + 1);
+            (skipped, 0)
+        }
+        _ => (2, 1),
+    }
+}
+
+/// Returns where an offset into a pattern falls in the value of the literal
+/// that it came from: every byte of a byte string became a char of the
+/// pattern, so the offset is counted in chars
+fn value_offset(pattern: &str, offset: usize, bytes: bool) -> usize {
+    if bytes {
+        pattern.get(..offset).map_or(offset, |s| s.chars().count())
+    } else {
+        offset
+    }
+}
+
+/// Parses a map of names to string literals, such as `{ v: "aeiou" }`
+fn parse_map(input: ParseStream) -> parse::Result<Vec<(Ident, LitStr)>> {
+    let content;
+    braced!(content in input);
+    let entries =
+        Punctuated::<(Ident, LitStr), Token![,]>::parse_terminated_with(&content, |input| {
+            let name = input.parse()?;
+            input.parse::<Token![:]>()?;
+            Ok((name, input.parse()?))
+        })?;
+    let mut seen = Vec::<&Ident>::new();
+    for (name, _) in &entries {
+        if seen.contains(&name) {
+            return Err(Error::new(name.span(), "duplicate name"));
+        }
+        seen.push(name);
+    }
+    Ok(entries.into_iter().collect())
+}
+
+/// Parses a range of counts such as `1`, `1..`, `1..4`, or `1..=3`, returning
+/// its lower bound and its inclusive upper bound, if it has one
+fn parse_counts(input: ParseStream) -> parse::Result<(usize, Option<usize>)> {
+    let literal = input.parse::<LitInt>()?;
+    let lower = literal.base10_parse()?;
+    let upper = if input.peek(Token![..=]) {
+        input.parse::<Token![..=]>()?;
+        Some(input.parse::<LitInt>()?.base10_parse()?)
+    } else if input.peek(Token![..]) {
+        input.parse::<Token![..]>()?;
+        if input.peek(LitInt) {
+            let upper = input.parse::<LitInt>()?.base10_parse::<usize>()?;
+            Some(
+                upper
+                    .checked_sub(1)
+                    .ok_or_else(|| Error::new(literal.span(), "range should be nonempty"))?,
+            )
+        } else {
+            None
+        }
+    } else {
+        Some(lower)
+    };
+    match upper {
+        Some(upper) if upper < lower => Err(Error::new(literal.span(), "range should be nonempty")),
+        _ => Ok((lower, upper)),
+    }
+}
+
+/// Describes a number of candidates, which saturates at u128::MAX
+fn candidates(count: u128) -> String {
+    if count == u128::MAX {
+        "at least u128::MAX candidates".to_string()
+    } else {
+        format!("{} candidates", count)
+    }
+}
+
+/// Returns the value of a literal that should be the body of a character
+/// class, adding its warnings to `warnings`
+fn class_body(
+    body: &LitStr,
+    opts: &Options,
+    warnings: &mut Vec<(Span, String)>,
+) -> parse::Result<String> {
+    let value = body.value();
+    let found = Lexer::check_class(&value, opts)
+        .map_err(|e| Error::new(make_span(&body.token(), e.offset), e.msg))?;
+    warnings.extend(
+        found
+            .into_iter()
+            .map(|w| (make_span(&body.token(), w.offset), w.msg.to_string())),
+    );
+    Ok(value)
 }
 
 impl Parse for Config {
     fn parse(input: ParseStream) -> parse::Result<Self> {
-        let literal = input.parse::<LitStr>()?;
-        let pattern = literal.value();
+        let base_dir = PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap_or_default());
+        let mut mask_file = None;
+        // the literal that errors in the pattern point into, unless it's read
+        // from a file
+        let (source, pattern, span, token, bytes) = if input.peek(Ident) {
+            let key = input.parse::<Ident>()?;
+            input.parse::<Token![=]>()?;
+            let literal = input.parse::<LitStr>()?;
+            match key.to_string().as_str() {
+                "mask" => (
+                    Source::Mask,
+                    literal.value(),
+                    literal.span(),
+                    Some(literal.token()),
+                    false,
+                ),
+                "mask_file" => {
+                    let path = base_dir.join(literal.value());
+                    let src = std::fs::read_to_string(&path).map_err(|e| {
+                        Error::new(literal.span(), format!("couldn't read mask file: {}", e))
+                    })?;
+                    mask_file = Some(path);
+                    (Source::Mask, src, literal.span(), None, false)
+                }
+                _ => {
+                    return Err(Error::new(
+                        key.span(),
+                        "expected a pattern, `mask`, or `mask_file`",
+                    ))
+                }
+            }
+        } else {
+            match input.parse::<Lit>()? {
+                Lit::Str(literal) => (
+                    Source::Pattern,
+                    literal.value(),
+                    literal.span(),
+                    Some(literal.token()),
+                    false,
+                ),
+                Lit::ByteStr(literal) => (
+                    Source::Pattern,
+                    literal.value().into_iter().map(char::from).collect(),
+                    literal.span(),
+                    Some(literal.token()),
+                    true,
+                ),
+                literal => {
+                    return Err(Error::new(
+                        literal.span(),
+                        "expected a string or byte string literal",
+                    ))
+                }
+            }
+        };
         input.parse::<Token![,]>()?;
         let handler = input.parse::<Ident>()?;
         input.parse::<Token![,]>()?;
         let abort = input.parse::<LitBool>()?.value;
         input.parse::<Token![,]>()?;
         let threaded = input.parse::<LitBool>()?.value;
-        let num_threads = num_cpus::get();
-        let part = if !threaded {
-            Partition::None
-        } else {
-            Partition::Naive
+        let mut opts = Options {
+            bytes,
+            base_dir,
+            ..Options::default()
         };
-        let result = Final::new(&pattern, num_threads, part).map_err(|e: ParseErr| {
-            Error::new(make_span(&pattern, e.offset, literal.span()), e.msg)
+        let mut defs = vec![];
+        let mut limits = Limits::default();
+        let mut limit_span = None;
+        let mut policy_span = None;
+        let mut report_keyspace = false;
+        let mut backend = None;
+        let mut warnings = vec![];
+        // optional trailing arguments look like `name = value`
+        while !input.is_empty() {
+            input.parse::<Token![,]>()?;
+            if input.is_empty() {
+                break;
+            }
+            let key = input.parse::<Ident>()?;
+            input.parse::<Token![=]>()?;
+            match key.to_string().as_str() {
+                "classes" => {
+                    for (name, body) in parse_map(input)? {
+                        let value = class_body(&body, &opts, &mut warnings)?;
+                        opts.classes.insert(name.to_string(), value);
+                    }
+                }
+                "leet" => {
+                    for (name, body) in parse_map(input)? {
+                        let letter = name.to_string();
+                        let mut chars = letter.chars();
+                        let c = match (chars.next(), chars.next()) {
+                            (Some(c), None) => c,
+                            _ => {
+                                return Err(Error::new(
+                                    name.span(),
+                                    "leetspeak substitutions should be for single letters",
+                                ))
+                            }
+                        };
+                        let value = class_body(&body, &opts, &mut warnings)?;
+                        opts.leet.insert(c, value);
+                    }
+                }
+                "length" => {
+                    let (lower, upper) = parse_counts(input)?;
+                    limits.length = Some(lower..=upper.unwrap_or(usize::MAX));
+                    limit_span = Some(key.span());
+                }
+                "policy" => {
+                    let content;
+                    braced!(content in input);
+                    let rules =
+                        Punctuated::<_, Token![,]>::parse_terminated_with(&content, |input| {
+                            let body = input.parse::<LitStr>()?;
+                            input.parse::<Token![:]>()?;
+                            Ok((body, parse_counts(input)?))
+                        })?;
+                    if rules.len() > 8 {
+                        return Err(Error::new(
+                            key.span(),
+                            "policies should have at most 8 rules",
+                        ));
+                    }
+                    let mut num_states = 1usize << rules.len();
+                    for (body, (min, max)) in rules {
+                        let value = body.value();
+                        let members = Lexer::class_bytes(&value, &opts)
+                            .map_err(|e| Error::new(make_span(&body.token(), e.offset), e.msg))?;
+                        num_states = num_states.saturating_mul(max.map_or(min, |max| max + 1) + 1);
+                        limits.policy.push(Rule { members, min, max });
+                    }
+                    if num_states > 1 << 20 {
+                        return Err(Error::new(key.span(), "policy has too many states"));
+                    }
+                    limit_span = Some(key.span());
+                    policy_span = Some(key.span());
+                }
+                "shortest_first" => limits.shortest_first = input.parse::<LitBool>()?.value,
+                "max_grown_len" => {
+                    limits.max_grown_len = input.parse::<LitInt>()?.base10_parse()?
+                }
+                "timeout" => {
+                    let secs = input.parse::<LitInt>()?.base10_parse()?;
+                    limits.timeout = Some(Duration::from_secs(secs));
+                }
+                "report_keyspace" => report_keyspace = input.parse::<LitBool>()?.value,
+                "backend" => backend = Some(input.parse::<Ident>()?),
+                "defs" => {
+                    for (name, body) in parse_map(input)? {
+                        opts.defs.insert(name.to_string(), body.value());
+                        defs.push(body);
+                    }
+                }
+                _ => return Err(Error::new(key.span(), "unknown option")),
+            }
+        }
+        // definitions can refer to each other, so they're checked once
+        // they've all been parsed
+        for body in defs {
+            let value = body.value();
+            let found = Lexer::check_def(&value, &opts)
+                .map_err(|e| Error::new(make_span(&body.token(), e.offset), e.msg))?;
+            warnings.extend(
+                found
+                    .into_iter()
+                    .map(|w| (make_span(&body.token(), w.offset), w.msg.to_string())),
+            );
+        }
+        // the runs are only split across threads once the backend is picked,
+        // so that it doesn't depend on how many CPUs the build has
+        let mut result = match source {
+            Source::Pattern => Final::new(&pattern, &opts, &limits, 1, Partition::None),
+            Source::Mask => Mask::tokenize(&pattern).and_then(|(ctx, tokens)| {
+                Final::from_tokens(ctx, &tokens, &limits, 1, Partition::None)
+            }),
+        }
+        .map_err(|e: ParseErr| match &token {
+            Some(token) => Error::new(
+                make_span(token, value_offset(&pattern, e.offset, opts.bytes)),
+                e.msg,
+            ),
+            None => {
+                let line = pattern[..e.offset].matches('\n').count() + 1;
+                Error::new(span, format!("{} (line {})", e.msg, line))
+            }
         })?;
+        warnings.extend(result.warnings.iter().map(|w| {
+            let span = token.as_ref().map_or(span, |token| {
+                make_span(token, value_offset(&pattern, w.offset, opts.bytes))
+            });
+            (span, w.msg.to_string())
+        }));
+        result.files.extend(mask_file);
+        // the policy's tables are built for runs whose length is known
+        if let (Some(_), Some(span)) = (&result.growth, policy_span) {
+            return Err(Error::new(
+                span,
+                "policies can't be combined with open-ended ranges",
+            ));
+        }
+        if let (true, Some(span)) = (result.tasks.iter().all(Vec::is_empty), limit_span) {
+            return Err(Error::new(span, "no candidates satisfy the limits"));
+        }
+        let supported = result.growth.is_none() && result.tasks[0].iter().all(odometer_supports);
+        let odometer = match backend {
+            Some(backend) if backend == "odometer" => {
+                if !supported {
+                    return Err(Error::new(
+                        backend.span(),
+                        "the odometer only supports classes and backreferences",
+                    ));
+                }
+                true
+            }
+            Some(backend) if backend == "unrolled" => false,
+            Some(backend) => {
+                return Err(Error::new(
+                    backend.span(),
+                    "expected `odometer` or `unrolled`",
+                ))
+            }
+            // every change becomes a loop, in every thread that runs it
+            None => {
+                let loops: usize = result.tasks[0].iter().map(|run| run.changes.len()).sum();
+                if loops > ODOMETER_THRESHOLD && !supported {
+                    warnings.push((
+                        span,
+                        format!(
+                            "pattern unrolls into {} loops, which is slow to compile, but the odometer only supports classes and backreferences",
+                            loops
+                        ),
+                    ));
+                }
+                loops > ODOMETER_THRESHOLD && supported
+            }
+        };
+        if threaded {
+            result.partition(num_cpus::get());
+        }
+        if report_keyspace {
+            let threads: Vec<_> = result
+                .keyspace
+                .iter()
+                .enumerate()
+                .map(|(i, &count)| format!("thread {}: {}", i, candidates(count)))
+                .collect();
+            let total = result
+                .keyspace
+                .iter()
+                .fold(0, |a, &b| u128::saturating_add(a, b));
+            warnings.push((
+                span,
+                format!("keyspace is {} ({})", candidates(total), threads.join(", ")),
+            ));
+        }
 
         Ok(Config {
             handler,
             result,
             abort,
             threaded,
+            warnings,
+            odometer,
         })
     }
 }
 
+/// Returns code that stops bonking once `check` is true
+fn stop_if(check: TokenStream2, abort: bool, threaded: bool) -> TokenStream2 {
+    if abort {
+        quote! {
+            if #check {
+                ::std::process::exit(0);
+            }
+        }
+    } else if threaded {
+        quote! {
+            if flag.load(::std::sync::atomic::Ordering::Relaxed) || #check {
+                // this will cause unnecessary writes in threads that
+                // haven't finished, but the overhead is probably negligible?
+                flag.store(true, ::std::sync::atomic::Ordering::Relaxed);
+                return;
+            }
+        }
+    } else {
+        quote! {
+            if #check {
+                return;
+            }
+        }
+    }
+}
+
+/// Returns whether the odometer can enumerate a run, which it can if every
+/// change draws from a class without any constraints
+fn odometer_supports(run: &Run) -> bool {
+    run.pools.is_empty()
+        && run.compliance.is_none()
+        && run
+            .changes
+            .iter()
+            .all(|change| matches!(change.values, Values::Class(_)) && change.distinct.is_empty())
+}
+
+/// Returns code that enumerates the runs with the odometer in the bonk
+/// crate, which describes each run with data rather than nested loops
+fn odometer(
+    runs: &[Run],
+    handler: &Ident,
+    abort: bool,
+    threaded: bool,
+    timeout: bool,
+) -> TokenStream2 {
+    let runs = runs.iter().map(|run| {
+        let len = run.len;
+        let inits = run
+            .inits
+            .iter()
+            .map(|Init { buf_idx, val }| quote! { (#buf_idx, #val) });
+        let slots = run.changes.iter().map(|change| {
+            let class_ident = match change.values {
+                Values::Class(id) => format_ident!("CLASS_{}", id),
+                _ => unreachable!("the odometer only enumerates classes"),
+            };
+            let Change {
+                buf_idx,
+                width,
+                lower,
+                upper,
+                ref mirrors,
+                ..
+            } = *change;
+            quote! {
+                ::bonk::odometer::Slot {
+                    buf_idx: #buf_idx,
+                    mirrors: &[#(#mirrors),*],
+                    class: #class_ident,
+                    width: #width,
+                    lower: #lower,
+                    upper: #upper,
+                }
+            }
+        });
+        quote! {
+            ::bonk::odometer::Run {
+                len: #len,
+                inits: &[#(#inits),*],
+                slots: &[#(#slots),*],
+            }
+        }
+    });
+    // threads that bonk without aborting check the flag for every candidate,
+    // not just every run
+    let flag = if !abort && threaded {
+        quote! { flag.load(::std::sync::atomic::Ordering::Relaxed) || }
+    } else {
+        quote! {}
+    };
+    // a deadline that passes stops the odometer like a handler would, but
+    // without counting as a find
+    let (tick, found, passed) = if timeout {
+        (
+            quote! { deadline.tick() || },
+            quote! { && !deadline.passed() },
+            quote! {
+                if deadline.passed() {
+                    return;
+                }
+            },
+        )
+    } else {
+        (quote! {}, quote! {}, quote! {})
+    };
+    let body = stop_if(
+        quote! {
+            ::bonk::odometer::run(run, &mut buf, |candidate| {
+                #tick #flag <#handler as ::bonk::Bonk>::check(&mut bonker, candidate)
+            }) #found
+        },
+        abort,
+        threaded,
+    );
+    quote! {
+        let runs = [#(#runs),*];
+        for run in &runs {
+            #body
+            #passed
+        }
+    }
+}
+
+/// Expands to code that passes every candidate of a pattern to a handler's
+/// `Bonk::check`, as in `bonk!("[ab]\\d", Handler, abort, threaded)`, where
+/// the pattern can also be `mask = "..."` or `mask_file = "..."`.
+///
+/// Optional trailing arguments look like `name = value`:
+///
+/// - `classes = { name: "..." }` and `leet = { a: "..." }` define classes
+///   and leetspeak substitutions
+/// - `defs = { name: "..." }` defines patterns that others can refer to
+/// - `length = lower..upper` skips candidates with other lengths
+/// - `policy = { "class": lower..upper }` skips candidates with too few or
+///   too many members of each class
+/// - `shortest_first = bool`, `report_keyspace = bool` and
+///   `backend = odometer | unrolled`
+/// - `max_grown_len = n` is the longest that an open-ended range makes
+///   candidates when `length` doesn't have an upper bound (256 by default)
+/// - `timeout = secs` stops enumerating after that many seconds, returning
+///   like a handler that found what it was looking for, but without exiting
+///   if `abort` is true
+///
+/// An open-ended range such as `{1,}` is only accepted at the very end of the
+/// pattern, so `[ab]{1,}x` is an error, and not inside groups or definitions.
+/// Its candidates stop at the end of `length`, or at `max_grown_len` bytes if
+/// `length` doesn't have an upper bound.
 #[proc_macro]
 pub fn bonk(input: TokenStream) -> TokenStream {
     let Config {
         handler,
         abort,
         threaded,
-        result: Final {
-            tasks,
-            statics,
-            max_size,
-        },
+        warnings,
+        odometer,
+        result:
+            Final {
+                tasks,
+                statics,
+                words,
+                max_size,
+                files,
+                policy,
+                growth,
+                keyspace,
+                timeout,
+                ..
+            },
     } = parse_macro_input!(input as Config);
+    let keyspace = keyspace.iter().fold(0, |a, &b| u128::saturating_add(a, b));
 
-    let statics = statics.into_iter().map(|(k, v)| {
+    // proc macros can't emit warnings yet, so each one is a use of a
+    // deprecated function whose note is the message
+    let warnings = warnings.into_iter().map(|(span, msg)| {
+        quote_spanned! {span=>
+            {
+                #[deprecated(note = #msg)]
+                fn warning() {}
+                warning();
+            }
+        }
+    });
+
+    let statics = statics.into_iter().map(|((k, _), v)| {
         let ident = format_ident!("CLASS_{}", v);
+        let k = Literal::byte_string(&k);
+        quote! {
+            static #ident: &[u8] = #k;
+        }
+    });
+    let words = words.into_iter().map(|((members, offsets), id)| {
+        let words_ident = format_ident!("WORDS_{}", id);
+        let offsets_ident = format_ident!("OFFSETS_{}", id);
+        let members = Literal::byte_string(&members);
+        quote! {
+            static #words_ident: &[u8] = #members;
+            static #offsets_ident: &[usize] = &[#(#offsets),*];
+        }
+    });
+    let policy_tables = policy.map(|Policy { masks, next, .. }| {
+        let num_states = next.len();
+        let num_masks = next[0].len();
+        let next = next.iter().map(|row| quote! { [#(#row),*] });
         quote! {
-            static #ident: &[u8] = #k.as_bytes();
+            static POLICY_MASKS: [u8; 256] = [#(#masks),*];
+            static POLICY_NEXT: [[usize; #num_masks]; #num_states] = [#(#next),*];
         }
     });
+    // runs share the table for the states after a change whenever they
+    // have the same one
+    let mut feasible_ids = HashMap::new();
+    let mut feasible_tables = vec![];
+    for compliance in tasks.iter().flatten().flat_map(|run| &run.compliance) {
+        for feasible in compliance.feasible.iter().flatten() {
+            feasible_ids.entry(feasible.clone()).or_insert_with(|| {
+                let feasible_ident = format_ident!("FEASIBLE_{}", feasible_tables.len());
+                let num_states = feasible.len();
+                feasible_tables.push(quote! {
+                    static #feasible_ident: [bool; #num_states] = [#(#feasible),*];
+                });
+                feasible_ident
+            });
+        }
+    }
     let num_threads = tasks.len();
     let tasks = tasks.into_iter().enumerate().map(|(thread_id, task)| {
-        let task = task.into_iter().map(
-            |Run {
-                 len,
-                 inits,
-                 changes,
-             }| {
-                let assignments = inits
-                    .into_iter()
-                    .map(|Init { buf_idx, val }| quote! { buf[#buf_idx] = #val; });
-                let check = quote! {
-                    <#handler as ::bonk::Bonk>::check(&mut bonker, &buf[0..#len])
-                };
-                let body = if abort {
-                    quote! {
-                        if #check {
-                            ::std::process::exit(0);
+        let shortest = task.iter().map(|run| run.len).min();
+        let odometer = if odometer {
+            Some(self::odometer(
+                &task,
+                &handler,
+                abort,
+                threaded,
+                timeout.is_some(),
+            ))
+        } else {
+            None
+        };
+        let task = task.into_iter().map(|run| {
+            let Run {
+                len,
+                ref changes,
+                ref pools,
+                ref compliance,
+                ref shifts,
+                ref lengths,
+                ref first_grown,
+                ..
+            } = run;
+            let value_ident = |j: usize| format_ident!("c_{}", changes[j].buf_idx);
+            // words move everything after them, by however much wider their
+            // value is than their change's width, which `o_{k}` adds up for
+            // the shifts up to the one with index k
+            let at = |buf_idx: usize| match run.shift_before(buf_idx) {
+                Some(k) => {
+                    let offset_ident = format_ident!("o_{}", k);
+                    quote! { #buf_idx + #offset_ident }
+                }
+                None => quote! { #buf_idx },
+            };
+            let moved = match shifts.len() {
+                0 => quote! {},
+                n => {
+                    let offset_ident = format_ident!("o_{}", n - 1);
+                    quote! { + #offset_ident }
+                }
+            };
+            // everything besides the changes' own values is written as soon
+            // as the changes that it depends on have taken their values
+            let writes: Vec<_> = run
+                .writes()
+                .into_iter()
+                .map(|writes| {
+                    let writes = writes.into_iter().map(|write| match write {
+                        Write::Shift(k) => {
+                            let j = shifts[k].change;
+                            let (value_ident, width) = (value_ident(j), changes[j].width);
+                            let offset_ident = format_ident!("o_{}", k);
+                            let before = k.checked_sub(1).map(|k| {
+                                let before_ident = format_ident!("o_{}", k);
+                                quote! { #before_ident + }
+                            });
+                            quote! { let #offset_ident = #before #value_ident.len() - #width; }
+                        }
+                        Write::Init(i) => {
+                            let Init { buf_idx, val } = run.inits[i];
+                            let at = at(buf_idx);
+                            quote! { buf[#at] = #val; }
                         }
+                        Write::Mirror { change: j, buf_idx } => {
+                            let (value_ident, width, to) =
+                                (value_ident(j), changes[j].width, at(buf_idx));
+                            match changes[j].values {
+                                Values::Class(_) if width == 1 => {
+                                    quote! { buf[#to] = #value_ident; }
+                                }
+                                Values::Class(_) => quote! {
+                                    buf[#to..#to + #width].copy_from_slice(#value_ident);
+                                },
+                                Values::Words(_) => quote! {
+                                    buf[#to..#to + #value_ident.len()].copy_from_slice(#value_ident);
+                                },
+                                _ => {
+                                    let from = at(changes[j].buf_idx);
+                                    quote! { buf.copy_within(#from..#from + #width, #to); }
+                                }
+                            }
+                        }
+                    });
+                    quote! { #(#writes)* }
+                })
+                .collect();
+            // each pool counts how many times its members have been drawn
+            let pools = pools.iter().enumerate().map(|(k, limits)| {
+                let used_ident = format_ident!("used_{}", k);
+                let pool_ident = format_ident!("pool_{}", k);
+                let n = limits.len();
+                quote! {
+                    let mut #used_ident = [0usize; #n];
+                    let #pool_ident = [#(#limits),*];
+                }
+            });
+            // with an open-ended range, `extra` more members are added to
+            // the end of the run
+            let end = match &growth {
+                Some(_) => quote! { len #moved },
+                None => quote! { #len #moved },
+            };
+            let check = quote! {
+                <#handler as ::bonk::Bonk>::check(&mut bonker, &buf[0..#end])
+            };
+            let body = stop_if(check, abort, threaded);
+            let body = match timeout {
+                Some(_) => quote! {
+                    if deadline.tick() {
+                        return;
                     }
-                } else if threaded {
+                    #body
+                },
+                None => body,
+            };
+            // the changes that count towards the policy each keep track of
+            // the state after the bytes they write (once more for each
+            // mirror), and skip values that lead to a state that can't
+            // follow every rule
+            let mut steps = vec![];
+            if let Some(Compliance { start, feasible }) = compliance {
+                let mut state = quote! { #start };
+                for (j, (change, feasible)) in changes.iter().zip(feasible).enumerate() {
+                    steps.push(feasible.as_ref().map(|feasible| {
+                        let at = at(change.buf_idx);
+                        let end = match change.values {
+                            Values::Words(_) => {
+                                let value_ident = value_ident(j);
+                                quote! { #at + #value_ident.len() }
+                            }
+                            _ => {
+                                let width = change.width;
+                                quote! { #at + #width }
+                            }
+                        };
+                        let times = 1 + change.mirrors.len();
+                        let state_ident = format_ident!("s_{}", change.buf_idx);
+                        let feasible_ident = &feasible_ids[feasible];
+                        let before = std::mem::replace(&mut state, quote! { #state_ident });
+                        quote! {
+                            let mut #state_ident = #before;
+                            for _ in 0..#times {
+                                for &b in &buf[#at..#end] {
+                                    #state_ident = POLICY_NEXT[#state_ident][POLICY_MASKS[b as usize] as usize];
+                                }
+                            }
+                            if !#feasible_ident[#state_ident] {
+                                continue;
+                            }
+                        }
+                    }));
+                }
+            } else {
+                steps.resize(changes.len(), None);
+            }
+            // the members added to the end count like an odometer, with
+            // the last one changing the fastest, and the first one only
+            // taking this thread's share of the members
+            let body = match &growth {
+                Some(Growth {
+                    class_id,
+                    width,
+                    len: num_members,
+                    ..
+                }) => {
+                    let class_ident = format_ident!("CLASS_{}", class_id);
+                    let share = first_grown.clone().unwrap_or(0..*num_members);
+                    let (lower, upper) = (share.start, share.end);
                     quote! {
-                        if flag.load(::std::sync::atomic::Ordering::Relaxed) || #check {
-                            // this will cause unnecessary writes in threads that
-                            // haven't finished, but the overhead is probably negligible?
-                            flag.store(true, ::std::sync::atomic::Ordering::Relaxed);
-                            return;
+                        odometer.clear();
+                        odometer.resize(extra, 0);
+                        if extra > 0 {
+                            odometer[0] = #lower;
                         }
+                        for (i, &m) in odometer.iter().enumerate() {
+                            let (at, m) = (#len #moved + i * #width, m * #width);
+                            buf[at..at + #width].copy_from_slice(&#class_ident[m..m + #width]);
+                        }
+                        'odometer: loop {
+                            #body
+                            let mut i = extra;
+                            loop {
+                                if i == 0 {
+                                    break 'odometer;
+                                }
+                                i -= 1;
+                                odometer[i] += 1;
+                                if i == 0 && odometer[0] == #upper {
+                                    break 'odometer;
+                                }
+                                if odometer[i] == #num_members {
+                                    odometer[i] = 0;
+                                }
+                                let (at, m) = (#len #moved + i * #width, odometer[i] * #width);
+                                buf[at..at + #width].copy_from_slice(&#class_ident[m..m + #width]);
+                                if odometer[i] != 0 {
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                }
+                None => body,
+            };
+            // words make some candidates longer than others, so when only
+            // some of them fit the limits, each one is checked
+            let body = match lengths {
+                Some(lengths) => {
+                    let mut bounds = vec![];
+                    if *lengths.start() > 0 {
+                        let min = lengths.start();
+                        bounds.push(quote! { #end >= #min });
+                    }
+                    if *lengths.end() < usize::MAX {
+                        let max = lengths.end();
+                        bounds.push(quote! { #end <= #max });
                     }
-                } else {
                     quote! {
-                        if #check {
-                            return;
+                        if #(#bounds)&&* {
+                            #body
                         }
                     }
-                };
-                let loops = changes.into_iter().rev().fold(
-                    body,
-                    |acc,
-                     Change {
-                         class_id,
-                         buf_idx,
-                         lower,
-                         upper,
-                     }| {
-                        let class_ident = format_ident!("CLASS_{}", class_id);
-                        let value_ident = format_ident!("c_{}", buf_idx);
+                }
+                None => body,
+            };
+            let loops = changes.iter().enumerate().zip(steps).rev().fold(
+                body,
+                |acc, ((j, change), step)| {
+                    let Change {
+                        ref values,
+                        buf_idx,
+                        width,
+                        lower,
+                        upper,
+                        ref distinct,
+                        pool,
+                        ..
+                    } = *change;
+                    let value_ident = value_ident(j);
+                    let at = at(buf_idx);
+                    let (values, assign) = match *values {
+                        Values::Number { start, digits, pad } => {
+                            let (first, last) = (start + lower as u64, start + (upper - 1) as u64);
+                            let radix = digits.len() as u64;
+                            let digits = Literal::byte_string(digits);
+                            (
+                                quote! { #first..=#last },
+                                quote! {
+                                    let mut n = #value_ident;
+                                    let end = #at + #width;
+                                    for i in (#at..end).rev() {
+                                        buf[i] = if n == 0 && i + 1 < end {
+                                            #pad
+                                        } else {
+                                            #digits[(n % #radix) as usize]
+                                        };
+                                        n /= #radix;
+                                    }
+                                },
+                            )
+                        }
+                        Values::Int { start, big_endian } => {
+                            let (first, last) = (start + lower as u64, start + (upper - 1) as u64);
+                            let int = format_ident!("u{}", width * 8);
+                            let to_bytes = if big_endian {
+                                quote! { to_be_bytes }
+                            } else {
+                                quote! { to_le_bytes }
+                            };
+                            (
+                                quote! { #first..=#last },
+                                quote! {
+                                    buf[#at..#at + #width].copy_from_slice(&(#value_ident as #int).#to_bytes());
+                                },
+                            )
+                        }
+                        Values::Class(class_id) if width == 1 => {
+                            let class_ident = format_ident!("CLASS_{}", class_id);
+                            (
+                                quote! { #class_ident[#lower..#upper].iter().copied() },
+                                quote! { buf[#at] = #value_ident; },
+                            )
+                        }
+                        Values::Class(class_id) => {
+                            let class_ident = format_ident!("CLASS_{}", class_id);
+                            let (start, stop) = (lower * width, upper * width);
+                            (
+                                quote! { #class_ident[#start..#stop].chunks_exact(#width) },
+                                quote! { buf[#at..#at + #width].copy_from_slice(#value_ident); },
+                            )
+                        }
+                        Values::Words(id) => {
+                            let words_ident = format_ident!("WORDS_{}", id);
+                            let offsets_ident = format_ident!("OFFSETS_{}", id);
+                            (
+                                quote! {
+                                    #offsets_ident[#lower..=#upper]
+                                        .windows(2)
+                                        .map(|w| &#words_ident[w[0]..w[1]])
+                                },
+                                quote! {
+                                    buf[#at..#at + #value_ident.len()].copy_from_slice(#value_ident);
+                                },
+                            )
+                        }
+                    };
+                    // single bytes are compared directly, and everything
+                    // else as slices
+                    let byte = |change: &Change| {
+                        matches!(change.values, Values::Class(_)) && change.width == 1
+                    };
+                    let distinct = distinct.iter().map(|&d| {
+                        let other = changes
+                            .iter()
+                            .find(|other| other.buf_idx == d)
+                            .expect("changes only differ from earlier changes");
+                        let other_ident = format_ident!("c_{}", d);
+                        match (byte(change), byte(other)) {
+                            (true, true) => quote! { #value_ident == #other_ident },
+                            (true, false) => {
+                                quote! { ::std::slice::from_ref(&#value_ident) == #other_ident }
+                            }
+                            (false, true) => {
+                                quote! { #value_ident == ::std::slice::from_ref(&#other_ident) }
+                            }
+                            (false, false) => quote! { #value_ident == #other_ident },
+                        }
+                    });
+                    let distinct: Vec<_> = distinct.collect();
+                    let guard = if distinct.is_empty() {
+                        quote! {}
+                    } else {
                         quote! {
-                            for #value_ident in #class_ident[#lower..#upper].iter().copied() {
-                                buf[#buf_idx] = #value_ident;
+                            if #(#distinct)||* {
+                                continue;
+                            }
+                        }
+                    };
+                    // the policy is stepped through the bytes once they're written
+                    let assign = quote! {
+                        #assign
+                        #step
+                    };
+                    let writes = &writes[j + 1];
+                    match pool {
+                        None => quote! {
+                            for #value_ident in #values {
+                                #guard
+                                #assign
+                                #writes
                                 #acc
                             }
+                        },
+                        Some(k) => {
+                            let used_ident = format_ident!("used_{}", k);
+                            let pool_ident = format_ident!("pool_{}", k);
+                            quote! {
+                                for (m, #value_ident) in #values.enumerate() {
+                                    let m = m + #lower;
+                                    if #used_ident[m] == #pool_ident[m] {
+                                        continue;
+                                    }
+                                    #guard
+                                    #assign
+                                    #used_ident[m] += 1;
+                                    #writes
+                                    #acc
+                                    #used_ident[m] -= 1;
+                                }
+                            }
                         }
-                    },
-                );
+                    }
+                },
+            );
+            let run_most = run.max_len() - len;
+            let prologue = &writes[0];
+            let run = quote! {
+                #prologue
+                #(#pools)*
+                #loops
+            };
+            match &growth {
+                Some(Growth { width, lengths, .. }) => {
+                    // words can make the run's candidates up to `most` bytes
+                    // longer than `len`
+                    let mut bounds = vec![];
+                    if *lengths.start() > 0 {
+                        let min = lengths.start();
+                        bounds.push(match run_most {
+                            0 => quote! { len >= #min },
+                            most => quote! { len + #most >= #min },
+                        });
+                    }
+                    let max = lengths.end();
+                    bounds.push(quote! { len <= #max });
+                    // only one thread tries the run without any members added
+                    if first_grown.as_ref().is_some_and(|first| first.start > 0) {
+                        bounds.push(quote! { extra > 0 });
+                    }
+                    let run = quote! {
+                        if #(#bounds)&&* {
+                            #run
+                        }
+                    };
+                    quote! {
+                        {
+                            let len = #len + extra * #width;
+                            #run
+                        }
+                    }
+                }
+                None => quote! {
+                    {
+                        #run
+                    }
+                },
+            }
+        });
+        // an open-ended range makes every run longer by one member at a
+        // time, until the shortest run is longer than the limit
+        let mut output = match (&growth, shortest, odometer) {
+            (_, _, Some(odometer)) => quote! {
+                let mut buf = [0u8; MAX_SIZE];
+                let mut bonker = <#handler as ::bonk::Bonk>::new(#thread_id);
+                #odometer
+            },
+            (Some(Growth { width, lengths, .. }), Some(shortest), None) => {
+                let max = lengths.end();
+                let stop = quote! {
+                    if #shortest + extra * #width > #max {
+                        break;
+                    }
+                };
                 quote! {
-                    #(#assignments)*
-                    #loops
+                    let mut buf = vec![0u8; MAX_SIZE];
+                    let mut bonker = <#handler as ::bonk::Bonk>::new(#thread_id);
+                    let mut odometer: Vec<usize> = vec![];
+                    for extra in 0usize.. {
+                        #stop
+                        buf.resize(MAX_SIZE + extra * #width, 0);
+                        #(#task)*
+                    }
                 }
+            }
+            _ => quote! {
+                let mut buf = [0u8; MAX_SIZE];
+                let mut bonker = <#handler as ::bonk::Bonk>::new(#thread_id);
+                #(#task)*
             },
-        );
-        let mut output = quote! {
-            let mut buf = [0u8; MAX_SIZE];
-            let mut bonker = <#handler as ::bonk::Bonk>::new(#thread_id);
-            #(#task)*
         };
         if !abort && threaded {
             let flag_ident = format_ident!("flag_{}", thread_id);
@@ -146,6 +1134,13 @@ pub fn bonk(input: TokenStream) -> TokenStream {
                 #output
             }
         }
+        // every thread looks at the clock on its own
+        if timeout.is_some() {
+            output = quote! {
+                let mut deadline = deadline;
+                #output
+            }
+        }
         if threaded {
             let thread_ident = format_ident!("t_{}", thread_id);
             output = quote! {
@@ -163,9 +1158,23 @@ pub fn bonk(input: TokenStream) -> TokenStream {
         }
         output
     });
+    // including the files makes cargo rebuild when they change
+    let files = files.iter().map(|path| {
+        let path = path.to_string_lossy();
+        quote! {
+            const _: &[u8] = include_bytes!(#path);
+        }
+    });
     let mut output = quote! {
+        #(#files)*
+        #(#warnings)*
         const MAX_SIZE: usize = #max_size;
+        #[allow(dead_code)]
+        const KEYSPACE: u128 = #keyspace;
         #(#statics)*
+        #(#words)*
+        #policy_tables
+        #(#feasible_tables)*
         #(#tasks)*
     };
     if threaded {
@@ -184,5 +1193,12 @@ pub fn bonk(input: TokenStream) -> TokenStream {
             #output
         };
     }
+    if let Some(timeout) = timeout {
+        let (secs, nanos) = (timeout.as_secs(), timeout.subsec_nanos());
+        output = quote! {
+            let deadline = ::bonk::Deadline::new(::std::time::Duration::new(#secs, #nanos));
+            #output
+        };
+    }
     output.into()
 }
@@ -0,0 +1,163 @@
+/// A day in the proleptic Gregorian calendar, between the years 0 and 9999
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Date {
+    pub year: i64,
+    pub month: u32,
+    pub day: u32,
+}
+
+fn is_leap(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if is_leap(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Parses a fixed number of ASCII digits
+fn digits(s: &str, n: usize) -> Option<i64> {
+    if s.len() == n && s.bytes().all(|b| b.is_ascii_digit()) {
+        s.parse().ok()
+    } else {
+        None
+    }
+}
+
+impl Date {
+    /// Parses a date written as "YYYY-MM-DD", returning None if it isn't a
+    /// valid date
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut parts = s.split('-');
+        let year = digits(parts.next()?, 4)?;
+        let month = digits(parts.next()?, 2)? as u32;
+        let day = digits(parts.next()?, 2)? as u32;
+        if parts.next().is_some()
+            || !(1..=12).contains(&month)
+            || !(1..=days_in_month(year, month)).contains(&day)
+        {
+            return None;
+        }
+        Some(Self { year, month, day })
+    }
+    /// Returns the number of days since 1970-01-01
+    pub fn days(self) -> i64 {
+        // see http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+        let year = if self.month <= 2 {
+            self.year - 1
+        } else {
+            self.year
+        };
+        let era = year.div_euclid(400);
+        let year_of_era = year - era * 400;
+        let month = i64::from((self.month + 9) % 12);
+        let day_of_year = (153 * month + 2) / 5 + i64::from(self.day) - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        era * 146_097 + day_of_era - 719_468
+    }
+    /// Returns the date that is `days` days after 1970-01-01
+    pub fn from_days(days: i64) -> Self {
+        // see http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+        let days = days + 719_468;
+        let era = days.div_euclid(146_097);
+        let day_of_era = days - era * 146_097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month = (5 * day_of_year + 2) / 153;
+        let day = (day_of_year - (153 * month + 2) / 5 + 1) as u32;
+        let month = if month < 10 { month + 3 } else { month - 9 } as u32;
+        let year = year_of_era + era * 400 + i64::from(month <= 2);
+        Self { year, month, day }
+    }
+    /// Renders the date in a format where "YYYY", "YY", "MM", and "DD" are
+    /// the zero-padded year, year of the century, month, and day, "M" and
+    /// "D" are the month and day without padding, and anything else is
+    /// taken literally
+    pub fn render(self, format: &str) -> Vec<u8> {
+        let mut out = String::new();
+        let mut rest = format;
+        while let Some(c) = rest.chars().next() {
+            let (field, len) = if rest.starts_with("YYYY") {
+                (format!("{:04}", self.year), 4)
+            } else if rest.starts_with("YY") {
+                (format!("{:02}", self.year % 100), 2)
+            } else if rest.starts_with("MM") {
+                (format!("{:02}", self.month), 2)
+            } else if rest.starts_with("DD") {
+                (format!("{:02}", self.day), 2)
+            } else if c == 'M' {
+                (self.month.to_string(), 1)
+            } else if c == 'D' {
+                (self.day.to_string(), 1)
+            } else {
+                (c.to_string(), c.len_utf8())
+            };
+            out.push_str(&field);
+            rest = &rest[len..];
+        }
+        out.into_bytes()
+    }
+}
+
+/// Parses a point in time written as "YYYY-MM-DD", "YYYY-MM-DDTHH:MM:SS", or
+/// a number of seconds, returning the number of seconds since the Unix epoch
+pub fn timestamp(s: &str) -> Option<i64> {
+    if let Ok(secs) = s.parse::<i64>() {
+        return Some(secs).filter(|&secs| secs >= 0);
+    }
+    let (date, time) = match s.find('T') {
+        Some(i) => (&s[..i], &s[i + 1..]),
+        None => (s, "00:00:00"),
+    };
+    let mut parts = time.split(':');
+    let hours = digits(parts.next()?, 2)?;
+    let minutes = digits(parts.next()?, 2)?;
+    let seconds = digits(parts.next()?, 2)?;
+    if parts.next().is_some() || hours > 23 || minutes > 59 || seconds > 59 {
+        return None;
+    }
+    Some(Date::parse(date)?.days() * 86400 + hours * 3600 + minutes * 60 + seconds)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn days() {
+        let date = |year, month, day| Date { year, month, day };
+        for &(d, days) in &[
+            (date(1970, 1, 1), 0),
+            (date(2000, 3, 1), 11017),
+            (date(1969, 12, 31), -1),
+            (date(0, 1, 1), -719_528),
+        ] {
+            assert_eq!(d.days(), days);
+            assert_eq!(Date::from_days(days), d);
+        }
+        assert_eq!(Date::parse("2024-02-29"), Some(date(2024, 2, 29)));
+        assert_eq!(Date::parse("2023-02-29"), None);
+        assert_eq!(Date::parse("1900-02-29"), None);
+        assert_eq!(Date::parse("2023-13-01"), None);
+        assert_eq!(Date::parse("2023-1-01"), None);
+    }
+    #[test]
+    fn render() {
+        let d = Date::parse("2004-03-09").unwrap();
+        assert_eq!(d.render("YYYYMMDD"), b"20040309");
+        assert_eq!(d.render("DD/MM/YY"), b"09/03/04");
+        assert_eq!(d.render("D.M.YYYY é"), "9.3.2004 é".as_bytes());
+    }
+    #[test]
+    fn timestamps() {
+        assert_eq!(timestamp("1970-01-01"), Some(0));
+        assert_eq!(timestamp("2001-09-09T01:46:40"), Some(1_000_000_000));
+        assert_eq!(timestamp("1234"), Some(1234));
+        assert_eq!(timestamp("2001-09-09T24:00:00"), None);
+        assert_eq!(timestamp("2001-09-09T01:46"), None);
+    }
+}
@@ -0,0 +1,2166 @@
+use super::date::{self, Date};
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::fs;
+use std::iter::{self, Peekable};
+use std::path::PathBuf;
+use std::str::CharIndices;
+
+pub static DIGITS: &[u8] = b"0123456789";
+pub static UPPERCASE_ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+pub static LOWERCASE_ALPHABET: &[u8] = b"abcdefghijklmnopqrstuvwxyz";
+pub static LOWERCASE_HEX: &[u8] = b"0123456789abcdef";
+pub static UPPERCASE_HEX: &[u8] = b"0123456789ABCDEF";
+static ALPHANUMERIC: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789_";
+static LEET: &[(char, &[u8])] = &[
+    ('a', b"aA4@"),
+    ('b', b"bB8"),
+    ('e', b"eE3"),
+    ('g', b"gG9"),
+    ('i', b"iI1!"),
+    ('l', b"lL1"),
+    ('o', b"oO0"),
+    ('s', b"sS5$"),
+    ('t', b"tT7"),
+    ('z', b"zZ2"),
+];
+static PRINTABLE: &[u8] =
+    b" !\"#$%&'()*+,-./0123456789:;<=>?@ABCDEFGHIJKLMNOPQRSTUVWXYZ[\\]^_`abcdefghijklmnopqrstuvwxyz{|}~";
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct ParseErr {
+    pub msg: &'static str,
+    pub offset: usize,
+}
+
+impl ParseErr {
+    pub fn new(msg: &'static str, offset: usize) -> Self {
+        Self { msg, offset }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Token {
+    Class {
+        id: usize,
+        len: usize,
+        width: usize,
+    },
+    Range {
+        lower: usize,
+        upper: usize,
+    },
+    Char(u8),
+    Repeat(usize),
+    /// Repeats the token before it at least this many times, with the
+    /// length growing at runtime
+    AtLeast(usize),
+    Group(Vec<Vec<Token>>),
+    /// The `len` members of the words with this id, which have different
+    /// widths: `width` is the narrowest one's and `widest` the widest one's
+    Words {
+        id: usize,
+        len: usize,
+        width: usize,
+        widest: usize,
+    },
+    /// Marks the start of the capturing group with this id
+    Open(usize),
+    /// Marks the end of the capturing group with this id
+    Close(usize),
+    /// A copy of whatever the capturing group with this id produced
+    Backref(usize),
+    /// Every arrangement of a multiset, where the class with this id holds
+    /// each distinct member once and `counts` holds how often it appears
+    Anagram {
+        id: usize,
+        width: usize,
+        counts: Vec<usize>,
+    },
+    /// `len` consecutive integers counting up from `start`, each rendered in
+    /// `width` bytes with `digits` (whose length is the radix) and
+    /// left-padded with `pad`
+    Number {
+        start: u64,
+        len: usize,
+        width: usize,
+        digits: &'static [u8],
+        pad: u8,
+    },
+    /// `len` consecutive integers counting up from `start`, each written as
+    /// its raw bytes in a `width`-byte unsigned integer
+    Int {
+        start: u64,
+        len: usize,
+        width: usize,
+        big_endian: bool,
+    },
+    /// Marks the start of a span whose class positions take distinct values
+    BeginDistinct,
+    /// Marks the end of a span whose class positions take distinct values
+    EndDistinct,
+}
+
+impl Token {
+    fn is_varying(&self) -> bool {
+        matches!(
+            self,
+            Self::Repeat(_) | Self::Range { .. } | Self::AtLeast(_)
+        )
+    }
+}
+
+/// Maps the bytes and width of each class's members to the class's id
+pub type Dict = HashMap<(Cow<'static, [u8]>, usize), usize>;
+
+/// Returns a class token for members that are all `width` bytes long, laid
+/// out back to back in `class`, reusing the id of a class with the same
+/// members in the same order if there is one, since that's the order they're
+/// enumerated in
+pub fn intern(dict: &mut Dict, class: Cow<'static, [u8]>, width: usize) -> Token {
+    let len = class.len() / width;
+    let next_id = dict.len() + 1;
+    let id = *dict.entry((class, width)).or_insert(next_id);
+    Token::Class { len, id, width }
+}
+
+/// Maps the members of each set of words, laid out back to back, and the
+/// offsets that each member starts at (followed by where the last one ends)
+/// to the words' id
+pub type Words = HashMap<(Vec<u8>, Vec<usize>), usize>;
+
+/// Returns a token for members of different widths, reusing the id of the
+/// same members in the same order if there is one
+pub fn intern_words(words: &mut Words, members: &[Vec<u8>]) -> Token {
+    let offsets = iter::once(0)
+        .chain(members.iter().scan(0, |end, member| {
+            *end += member.len();
+            Some(*end)
+        }))
+        .collect();
+    let id = words.len() + 1;
+    let id = *words.entry((members.concat(), offsets)).or_insert(id);
+    Token::Words {
+        id,
+        len: members.len(),
+        width: members.iter().map(Vec::len).min().unwrap_or(1),
+        widest: members.iter().map(Vec::len).max().unwrap_or(1),
+    }
+}
+
+/// Everything besides tokens that the lexers for a pattern build up
+#[derive(Debug, Default)]
+pub struct Context {
+    pub dict: Dict,
+    pub words: Words,
+    /// Files that the pattern reads, so that the macro can track them
+    pub files: Vec<PathBuf>,
+    /// The number of capturing groups in the pattern and every definition
+    /// that it refers to, which is used to give each group a unique id
+    pub groups: usize,
+    /// Problems that don't stop the pattern from compiling, such as classes
+    /// with duplicate members
+    pub warnings: Vec<ParseErr>,
+    /// Where each of the pattern's tokens starts in the source, if the lexer
+    /// keeps track of it, so that errors about them can point there
+    pub offsets: Vec<usize>,
+}
+
+/// Returns the other case of a char, if it has exactly one
+fn swap_case(c: char) -> Option<char> {
+    let swapped: Vec<_> = if c.is_lowercase() {
+        c.to_uppercase().collect()
+    } else {
+        c.to_lowercase().collect()
+    };
+    match swapped[..] {
+        [other] if other != c => Some(other),
+        _ => None,
+    }
+}
+
+/// A single member of a character class, as written in the pattern
+#[derive(Clone, Copy)]
+enum Member {
+    Byte(u8),
+    Char(char),
+}
+
+impl Member {
+    /// The char this member stands for when used as a range bound, if it has one
+    fn char(self) -> Option<char> {
+        match self {
+            Self::Byte(b) if b.is_ascii() => Some(b as char),
+            Self::Byte(_) => None,
+            Self::Char(c) => Some(c),
+        }
+    }
+    fn bytes(self) -> Vec<u8> {
+        match self {
+            Self::Byte(b) => vec![b],
+            Self::Char(c) => c.to_string().into_bytes(),
+        }
+    }
+}
+
+/// Settings that change how a pattern is lexed
+#[derive(Default)]
+pub struct Options {
+    /// Byte mode: every char of the pattern is a single byte (U+0000 to
+    /// U+00FF), and negated classes are taken over all 256 bytes
+    pub bytes: bool,
+    /// User-defined classes, mapping each name to the body of a character
+    /// class (the part that would go between the brackets)
+    pub classes: HashMap<String, String>,
+    /// The directory that wordlist paths are relative to
+    pub base_dir: PathBuf,
+    /// Leetspeak substitutions that replace the built-in ones, mapping each
+    /// letter to the body of a character class of its variants
+    pub leet: HashMap<char, String>,
+    /// User-defined sub-patterns, mapping each name to its pattern
+    pub defs: HashMap<String, String>,
+}
+
+pub struct Lexer<'s, 'd> {
+    chars: Peekable<CharIndices<'s>>,
+    src: &'s str,
+    idx: usize,           // the index of the char that was just consumed
+    ctx: &'d mut Context, // this is a reference so we can use collect() and still use the Context
+    opts: &'d Options,
+    fold_case: bool,    // whether we're inside of a case-insensitive group
+    groups: Vec<usize>, // the ids of the capturing groups opened so far, by number
+    open: Vec<usize>,   // the numbers of the capturing groups that haven't been closed yet
+    defs: Vec<String>,  // the definitions that are being lexed, innermost last
+}
+
+impl<'s, 'd> Lexer<'s, 'd> {
+    pub fn tokenize(src: &str, opts: &Options) -> Result<(Context, Vec<Token>), ParseErr> {
+        if let Some(i) = src.char_indices().find_map(|(i, c)| {
+            if opts.bytes && u32::from(c) > 0xff {
+                Some(i)
+            } else {
+                None
+            }
+        }) {
+            return Err(ParseErr::new(
+                "byte patterns should only contain chars from U+0000 to U+00FF",
+                i,
+            ));
+        }
+
+        let mut ctx = Context::default();
+        let tokens = Lexer::new(src, &mut ctx, opts).collect::<Result<Vec<_>, _>>()?;
+        Self::validate(&tokens, None)?;
+        let (offsets, tokens) = tokens.into_iter().unzip();
+        ctx.offsets = offsets;
+
+        Ok((ctx, tokens))
+    }
+    /// Checks that the body of a definition lexes to a pattern that can be
+    /// used like a group, returning its warnings
+    pub fn check_def(body: &str, opts: &Options) -> Result<Vec<ParseErr>, ParseErr> {
+        let mut ctx = Context::default();
+        let tokens = Lexer::new(body, &mut ctx, opts).collect::<Result<Vec<_>, _>>()?;
+        Self::validate_def(&tokens)?;
+        Ok(ctx.warnings)
+    }
+    /// Checks that the body of a user-defined class lexes to a character
+    /// class, returning its warnings
+    pub fn check_class(body: &str, opts: &Options) -> Result<Vec<ParseErr>, ParseErr> {
+        let mut ctx = Context::default();
+        Lexer::class_body(body, &mut ctx, opts)?;
+        Ok(ctx.warnings)
+    }
+    /// Returns the members of a user-defined class whose members are all
+    /// single bytes
+    pub fn class_bytes(body: &str, opts: &Options) -> Result<Vec<u8>, ParseErr> {
+        let mut ctx = Context::default();
+        match Lexer::class_body(body, &mut ctx, opts)? {
+            Token::Class { width: 1, .. } => Ok(ctx
+                .dict
+                .into_iter()
+                .map(|((class, _), _)| class.into_owned())
+                .next()
+                .expect("we just lexed a class")),
+            _ => Err(ParseErr::new(
+                "class should only contain single-byte members",
+                0,
+            )),
+        }
+    }
+    /// Lexes the body of a user-defined class as if it were written between
+    /// brackets
+    fn class_body(body: &str, ctx: &mut Context, opts: &Options) -> Result<Token, ParseErr> {
+        let src = format!("{}]", body);
+        let mut lexer = Lexer::new(&src, ctx, opts);
+        let token = lexer.left_bracket()?;
+        if lexer.chars.next().is_some() {
+            return Err(lexer.err("unexpected ']'"));
+        }
+        Ok(token)
+    }
+    /// Lexes the body of a user-defined class that the pattern refers to;
+    /// its warnings were already given where it was defined, so they're
+    /// dropped
+    fn user_class(&mut self, body: &str) -> Result<Token, ParseErr> {
+        let warnings = self.ctx.warnings.len();
+        let token = Lexer::class_body(body, self.ctx, self.opts)?;
+        self.ctx.warnings.truncate(warnings);
+        Ok(token)
+    }
+    /// Checks that a sequence of tokens (either the whole pattern or one of
+    /// the alternatives in a group starting at `start`) is nonempty and that
+    /// every range or repeat follows something it can apply to
+    fn validate(tokens: &[(usize, Token)], start: Option<usize>) -> Result<(), ParseErr> {
+        if tokens
+            .first()
+            .ok_or_else(|| match start {
+                Some(i) => ParseErr::new("alternatives in a group should be nonempty", i),
+                None => ParseErr::new("pattern should be nonempty", 0),
+            })?
+            .1
+            .is_varying()
+        {
+            return Err(match start {
+                Some(i) => ParseErr::new(
+                    "alternatives in a group should begin with a character or character class",
+                    i,
+                ),
+                None => ParseErr::new(
+                    "pattern should begin with a character or character class",
+                    0,
+                ),
+            });
+        };
+
+        if let Some(i) = tokens.windows(2).find_map(|s| {
+            if s.iter().all(|t| t.1.is_varying()) {
+                Some(s[1].0)
+            } else {
+                None
+            }
+        }) {
+            return Err(ParseErr::new(
+                "ranges and repeats should be preceded by characters, character classes, or groups",
+                i,
+            ));
+        }
+        // the length of everything else is fixed at compile time, so only the
+        // end of the pattern can grow
+        if let Some(k) = tokens.iter().position(|t| matches!(t.1, Token::AtLeast(_))) {
+            let i = tokens[k].0;
+            if start.is_some() {
+                return Err(ParseErr::new(
+                    "open-ended ranges should not be inside groups",
+                    i,
+                ));
+            }
+            if k + 1 < tokens.len() {
+                return Err(ParseErr::new(
+                    "open-ended ranges should only come at the end of the pattern",
+                    i,
+                ));
+            }
+        }
+        if let [.., (_, before), (i, Token::AtLeast(_))] = tokens {
+            if !matches!(before, Token::Char(_) | Token::Class { .. }) {
+                return Err(ParseErr::new(
+                    "open-ended ranges should follow a character or character class",
+                    *i,
+                ));
+            }
+        }
+        Ok(())
+    }
+    /// Checks the tokens of a definition like those of a whole pattern, but
+    /// without open-ended ranges, since definitions are nested in a group
+    fn validate_def(tokens: &[(usize, Token)]) -> Result<(), ParseErr> {
+        Self::validate(tokens, None)?;
+        match tokens.last() {
+            Some(&(i, Token::AtLeast(_))) => Err(ParseErr::new(
+                "open-ended ranges should not be inside definitions",
+                i,
+            )),
+            _ => Ok(()),
+        }
+    }
+    /// Create a new Lexer from a &str
+    fn new(src: &'s str, ctx: &'d mut Context, opts: &'d Options) -> Self {
+        Self {
+            chars: src.char_indices().peekable(),
+            src,
+            idx: 0,
+            ctx,
+            opts,
+            fold_case: false,
+            groups: vec![],
+            open: vec![],
+            defs: vec![],
+        }
+    }
+
+    fn class(&mut self, class: Cow<'static, [u8]>) -> Token {
+        self.class_with_width(class, 1)
+    }
+    fn class_with_width(&mut self, class: Cow<'static, [u8]>, width: usize) -> Token {
+        intern(&mut self.ctx.dict, class, width)
+    }
+    /// Returns a class token for members of any width, in the order that
+    /// they're given; members with different widths become words
+    fn members(&mut self, members: Vec<Vec<u8>>) -> Token {
+        match members.first().map(Vec::len) {
+            Some(width) if members.iter().any(|member| member.len() != width) => {
+                intern_words(&mut self.ctx.words, &members)
+            }
+            width => self.class_with_width(members.concat().into(), width.unwrap_or(1)),
+        }
+    }
+    /// Returns a token for a literal char; chars that take up more than one
+    /// byte in UTF-8 become a group of their bytes, and chars with a case
+    /// become a class of both cases inside of a case-insensitive group
+    fn literal(&mut self, c: char) -> Token {
+        if self.fold_case && (c.is_ascii() || !self.opts.bytes) {
+            if let Some(other) = swap_case(c) {
+                return self.members(vec![Member::Char(c).bytes(), Member::Char(other).bytes()]);
+            }
+        }
+        if self.opts.bytes || c.is_ascii() {
+            Token::Char(c as u8)
+        } else {
+            Token::Group(vec![c.to_string().bytes().map(Token::Char).collect()])
+        }
+    }
+
+    /// Consumes char, setting the current index to the byte offset of said
+    /// char; callers of this function assume that there are more chars
+    /// to lex, so this will return an error if there are no more chars
+    fn consume_char(&mut self) -> Result<char, ParseErr> {
+        let (i, c) = self.chars.next().ok_or_else(|| self.eoi())?;
+        self.idx = i;
+        Ok(c)
+    }
+    /// Peeks char, then returns it if the predicate matches
+    fn consume_if<F>(&mut self, predicate: F) -> Result<Option<char>, ParseErr>
+    where
+        F: Fn(char) -> bool,
+    {
+        if let Some(&(_, c_inner)) = self.chars.peek() {
+            if (predicate)(c_inner) {
+                self.consume_char().expect("we just peeked");
+                Ok(Some(c_inner))
+            } else {
+                Ok(None)
+            }
+        } else {
+            Err(self.eoi())
+        }
+    }
+    /// Takes chars while they're ASCII digits, then parses to a usize; must
+    /// be positive
+    fn consume_number(&mut self) -> Result<usize, ParseErr> {
+        if self.consume_char()?.is_ascii_digit() {
+            let start = self.idx;
+            // NOTE: take_while isn't an option because we don't want to consume a non-digit,
+            // and we need to somehow advance the index to parse the substring
+            while self.consume_if(|c| c.is_ascii_digit())?.is_some() {}
+            self.src[start..=self.idx]
+                .parse()
+                .map_err(|_| ParseErr::new("range bounds should not exceed a usize", start))
+        } else {
+            Err(self.err("expected positive integer"))
+        }
+    }
+    /// helper method to create a ParseErr with the current index in the source
+    fn err(&self, msg: &'static str) -> ParseErr {
+        ParseErr::new(msg, self.idx)
+    }
+    /// helper method to create an "unexpected end of input" message
+    fn eoi(&self) -> ParseErr {
+        ParseErr::new("unexpected end of input", self.idx)
+    }
+    /// Transforms escaped characters into their specific meanings:
+    /// - "\\" is a literal backslash
+    /// - "\[" is a literal opening bracket
+    /// - "\]" is a literal closing bracket
+    /// - "\{" is a literal opening curly brace
+    /// - "\}" is a literal closing curly brace
+    /// - "\(" is a literal opening parenthesis
+    /// - "\)" is a literal closing parenthesis
+    /// - "\|" is a literal vertical bar
+    /// - "\?" is a literal question mark
+    /// - "\xNN" is the byte with the hex value NN
+    /// - "\n", "\r", "\t", and "\0" are a newline, carriage return, tab,
+    ///   and null byte respectively
+    /// - "\b" is a character class of every byte from 0x00 to 0xFF
+    /// - "\A" is a character class of [A-Z]
+    /// - "\a" is a character class of [a-z]
+    /// - "\d" is a character class of [0-9]
+    /// - "\h" is a character class of [0-9A-F]
+    /// - "\H" is a character class of [0-9a-f]
+    /// - "\w" is a character class of [A-Za-z0-9_]
+    /// - "\k{name}" is the user-defined character class called name
+    /// - "\f{path}" is every line of the wordlist at path
+    /// - "\l{word}" is word with each letter replaced by its leetspeak variants
+    /// - "\p{name}" is the user-defined sub-pattern called name
+    /// - "\1" through "\9" are backreferences to the capturing group with
+    ///   that number, which must be closed before the backreference
+    /// - "\m{word}" is every distinct arrangement of the chars in word
+    /// - "\i{lo..hi}" and "\i{lo..=hi}" are the integers in a range, which
+    ///   may be followed by a format like ":04x" (see `number`)
+    /// - "\u16le{lo..hi}" is the integers in a range as the raw bytes of a
+    ///   little-endian u16; u32 and u64 work too, and "be" is big-endian
+    /// - "\D{lo..hi}" is the valid dates in a range, which may be followed by
+    ///   a format like ":DD/MM/YY" (see `dates`)
+    /// - "\T{lo..hi}" is the Unix timestamps in a window, which may be
+    ///   followed by a format like ":msx" (see `timestamps`)
+    fn backslash(&mut self) -> Result<Token, ParseErr> {
+        match self.consume_char()? {
+            'l' => {
+                let (start, word) =
+                    self.braced("'\\l' should be followed by a word in curly braces")?;
+                if word.is_empty() {
+                    return Err(ParseErr::new("leetspeak words should be nonempty", start));
+                }
+                let word = word
+                    .chars()
+                    .map(|c| self.leet(c))
+                    .collect::<Result<_, _>>()?;
+                Ok(Token::Group(vec![word]))
+            }
+            'm' => {
+                let (start, word) =
+                    self.braced("'\\m' should be followed by a word in curly braces")?;
+                let mut members: Vec<Vec<u8>> = vec![];
+                let mut counts = vec![];
+                for c in word.chars() {
+                    let member = if self.opts.bytes {
+                        vec![c as u8]
+                    } else {
+                        Member::Char(c).bytes()
+                    };
+                    match members.iter().position(|m| *m == member) {
+                        Some(i) => counts[i] += 1,
+                        None => {
+                            members.push(member);
+                            counts.push(1);
+                        }
+                    }
+                }
+                let width = members
+                    .first()
+                    .ok_or_else(|| ParseErr::new("anagram words should be nonempty", start))?
+                    .len();
+                if members.iter().any(|m| m.len() != width) {
+                    return Err(ParseErr::new(
+                        "anagram words should only contain chars of the same width in UTF-8",
+                        start,
+                    ));
+                }
+                match self.class_with_width(members.concat().into(), width) {
+                    Token::Class { id, .. } => Ok(Token::Anagram { id, width, counts }),
+                    _ => unreachable!(),
+                }
+            }
+            'u' => self.int(),
+            'D' => {
+                let (start, range) =
+                    self.braced("'\\D' should be followed by a range of dates in curly braces")?;
+                self.dates(range, start)
+            }
+            'T' => {
+                let (start, range) = self
+                    .braced("'\\T' should be followed by a range of timestamps in curly braces")?;
+                Self::timestamps(range, start)
+            }
+            'i' => {
+                let (start, range) =
+                    self.braced("'\\i' should be followed by a range in curly braces")?;
+                Self::number(range, start)
+            }
+            'f' => {
+                let (start, path) =
+                    self.braced("'\\f' should be followed by a wordlist path in curly braces")?;
+                let path = self.opts.base_dir.join(path);
+                let contents =
+                    fs::read(&path).map_err(|_| ParseErr::new("couldn't read wordlist", start))?;
+                let mut words: Vec<_> = contents
+                    .split(|&b| b == b'\n')
+                    .map(|word| word.strip_suffix(b"\r").unwrap_or(word).to_vec())
+                    .filter(|word| !word.is_empty())
+                    .collect();
+                if words.is_empty() {
+                    return Err(ParseErr::new(
+                        "wordlist should contain at least one word",
+                        start,
+                    ));
+                }
+                // repeated words are only tried the first time
+                let len = words.len();
+                let mut seen = HashSet::new();
+                words.retain(|word| seen.insert(word.clone()));
+                if words.len() < len {
+                    self.ctx
+                        .warnings
+                        .push(ParseErr::new("wordlist has duplicate words", start));
+                }
+                self.ctx.files.push(path);
+                Ok(self.members(words))
+            }
+            'p' => {
+                let (start, name) =
+                    self.braced("'\\p' should be followed by a definition name in curly braces")?;
+                self.def(name, start)
+            }
+            'k' => {
+                let (start, name) =
+                    self.braced("'\\k' should be followed by a class name in curly braces")?;
+                let body = self
+                    .opts
+                    .classes
+                    .get(name)
+                    .ok_or_else(|| ParseErr::new("unknown class name", start))?;
+                self.user_class(body)
+            }
+            c @ '1'..='9' => {
+                let n = c as usize - '0' as usize;
+                if n > self.groups.len() || self.open.contains(&n) {
+                    return Err(self.err(
+                        "backreferences should refer to a group that was closed before them",
+                    ));
+                }
+                Ok(Token::Backref(self.groups[n - 1]))
+            }
+            c @ 'x' | c @ 'n' | c @ 'r' | c @ 't' | c @ '0' => self.byte_escape(c).map(Token::Char),
+            'b' => Ok(self.class((0..=0xff).collect::<Vec<_>>().into())),
+            'd' => Ok(self.class(DIGITS.into())),
+            'A' => Ok(self.class(UPPERCASE_ALPHABET.into())),
+            'a' => Ok(self.class(LOWERCASE_ALPHABET.into())),
+            'H' => Ok(self.class(UPPERCASE_HEX.into())),
+            'h' => Ok(self.class(LOWERCASE_HEX.into())),
+            'w' => Ok(self.class(ALPHANUMERIC.into())),
+            '\\' => Ok(Token::Char(b'\\')),
+            '[' => Ok(Token::Char(b'[')),
+            ']' => Ok(Token::Char(b']')),
+            '{' => Ok(Token::Char(b'{')),
+            '}' => Ok(Token::Char(b'}')),
+            '(' => Ok(Token::Char(b'(')),
+            ')' => Ok(Token::Char(b')')),
+            '|' => Ok(Token::Char(b'|')),
+            '?' => Ok(Token::Char(b'?')),
+            _ => Err(self.err(
+                "'\\' should be followed by a class name, a byte escape, or a special character",
+            )),
+        }
+    }
+    /// Returns a group for a reference to a definition, where `start` is the
+    /// offset of its name; the definition is lexed on its own, so its
+    /// groups aren't numbered along with the pattern's, and any errors
+    /// point at the reference
+    fn def(&mut self, name: &str, start: usize) -> Result<Token, ParseErr> {
+        let body = self
+            .opts
+            .defs
+            .get(name)
+            .ok_or_else(|| ParseErr::new("unknown definition name", start))?;
+        if self.defs.iter().any(|def| def == name) {
+            return Err(ParseErr::new(
+                "definitions should not refer to themselves",
+                start,
+            ));
+        }
+        // like user-defined classes, definitions have their warnings given
+        // where they're defined
+        let warnings = self.ctx.warnings.len();
+        let mut lexer = Lexer::new(body, self.ctx, self.opts);
+        lexer.fold_case = self.fold_case;
+        lexer.defs = self.defs.clone();
+        lexer.defs.push(name.to_string());
+        let tokens = lexer
+            .collect::<Result<Vec<_>, _>>()
+            .and_then(|tokens| Self::validate_def(&tokens).map(|_| tokens))
+            .map_err(|e| ParseErr::new(e.msg, start))?;
+        self.ctx.warnings.truncate(warnings);
+        Ok(Token::Group(vec![tokens
+            .into_iter()
+            .map(|(_, t)| t)
+            .collect()]))
+    }
+    /// Returns the token for a letter in a leetspeak word, preferring the
+    /// substitutions in the options over the built-in ones; letters without
+    /// any substitutions still come in both cases
+    fn leet(&mut self, c: char) -> Result<Token, ParseErr> {
+        let lower = c.to_ascii_lowercase();
+        if let Some(body) = self
+            .opts
+            .leet
+            .get(&c)
+            .or_else(|| self.opts.leet.get(&lower))
+        {
+            self.user_class(body)
+        } else if let Some(&(_, variants)) = LEET.iter().find(|&&(k, _)| k == c || k == lower) {
+            Ok(self.class(variants.into()))
+        } else {
+            let fold_case = std::mem::replace(&mut self.fold_case, true);
+            let token = self.literal(c);
+            self.fold_case = fold_case;
+            Ok(token)
+        }
+    }
+    /// Splits the bounds of a range token into its lower bound, upper bound,
+    /// and whether the upper bound is inclusive
+    fn range(bounds: &str, start: usize) -> Result<(&str, &str, bool), ParseErr> {
+        match bounds.find("..") {
+            Some(i) if bounds[i + 2..].starts_with('=') => {
+                Ok((&bounds[..i], &bounds[i + 3..], true))
+            }
+            Some(i) => Ok((&bounds[..i], &bounds[i + 2..], false)),
+            None => Err(ParseErr::new(
+                "ranges should look like 'lo..hi' or 'lo..=hi'",
+                start,
+            )),
+        }
+    }
+    /// Splits the argument of a range token into its bounds and the format
+    /// after the first ':', if there is one
+    fn format(arg: &str) -> (&str, &str) {
+        match arg.find(':') {
+            Some(i) => (&arg[..i], &arg[i + 1..]),
+            None => (arg, ""),
+        }
+    }
+    /// Returns the digits for a radix in a numeric format: 'd' (or nothing),
+    /// 'x', 'X', 'o', or 'b'
+    fn digits(radix: &str, start: usize) -> Result<&'static [u8], ParseErr> {
+        match radix {
+            "" | "d" => Ok(DIGITS),
+            "x" => Ok(LOWERCASE_HEX),
+            "X" => Ok(UPPERCASE_HEX),
+            "o" => Ok(&DIGITS[..8]),
+            "b" => Ok(&DIGITS[..2]),
+            _ => Err(ParseErr::new(
+                "numeric formats should end with 'd', 'x', 'X', 'o', or 'b'",
+                start,
+            )),
+        }
+    }
+    /// Returns the token for a numeric range such as "0..=9999:04", where
+    /// `start` is the offset of the range in the source. The bounds are
+    /// decimal, and the optional format after the ':' is a width (padded
+    /// with zeros if it starts with '0', otherwise with spaces) followed by
+    /// a radix (see `digits`)
+    /// Returns the first and last integer in a range with decimal bounds
+    fn integers(bounds: &str, start: usize) -> Result<(u64, u64), ParseErr> {
+        let (lower, upper, inclusive) = Self::range(bounds, start)?;
+        let bound = |s: &str| {
+            s.parse::<u64>()
+                .map_err(|_| ParseErr::new("range bounds should be decimal integers", start))
+        };
+        let lower = bound(lower)?;
+        let upper = match (bound(upper)?, inclusive) {
+            (upper, true) => Some(upper),
+            (upper, false) => upper.checked_sub(1),
+        };
+        match upper {
+            Some(upper) if lower <= upper => Ok((lower, upper)),
+            _ => Err(ParseErr::new("ranges should be nonempty", start)),
+        }
+    }
+    fn number(arg: &str, start: usize) -> Result<Token, ParseErr> {
+        let (bounds, format) = Self::format(arg);
+        let (lower, upper) = Self::integers(bounds, start)?;
+        let pad = if format.starts_with('0') { b'0' } else { b' ' };
+        let (width, radix) = format.split_at(
+            format
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(format.len()),
+        );
+        let width = if width.is_empty() {
+            0
+        } else {
+            width
+                .parse::<usize>()
+                .map_err(|_| ParseErr::new("invalid width in numeric format", start))?
+        };
+        let digits = Self::digits(radix, start)?;
+        Self::numbers(lower, upper, width, digits, pad, start)
+    }
+    /// Returns the token for the integers from `lower` to `upper` inclusive;
+    /// values with different rendered widths become the alternatives of a
+    /// group
+    fn numbers(
+        lower: u64,
+        upper: u64,
+        width: usize,
+        digits: &'static [u8],
+        pad: u8,
+        start: usize,
+    ) -> Result<Token, ParseErr> {
+        // split the range wherever the rendered width changes, which is at
+        // each power of the radix past the padded width
+        let radix = digits.len() as u128;
+        let mut alts = vec![];
+        let mut value = u128::from(lower);
+        while value <= u128::from(upper) {
+            let mut len = 1;
+            let mut next = radix;
+            while next <= value || len < width {
+                len += 1;
+                next = next.saturating_mul(radix);
+            }
+            let last = u128::from(upper).min(next - 1);
+            alts.push(vec![Token::Number {
+                start: value as u64,
+                len: usize::try_from(last - value + 1)
+                    .map_err(|_| ParseErr::new("numeric range is too large", start))?,
+                width: len,
+                digits,
+                pad,
+            }]);
+            value = last + 1;
+        }
+        Ok(match alts.len() {
+            1 => alts.remove(0).remove(0),
+            _ => Token::Group(alts),
+        })
+    }
+    /// Returns the token for an integer field such as "u32le{0..100000}",
+    /// starting after the 'u'
+    fn int(&mut self) -> Result<Token, ParseErr> {
+        let width = match self.consume_number()? {
+            16 => 2,
+            32 => 4,
+            64 => 8,
+            _ => return Err(self.err("integer fields should be 16, 32, or 64 bits")),
+        };
+        let big_endian = match (self.consume_char()?, self.consume_char()?) {
+            ('l', 'e') => false,
+            ('b', 'e') => true,
+            _ => return Err(self.err("integer fields should be 'le' or 'be'")),
+        };
+        let (start, bounds) =
+            self.braced("integer fields should be followed by a range in curly braces")?;
+        let (lower, upper) = Self::integers(bounds, start)?;
+        if width < 8 && upper >> (width * 8) != 0 {
+            return Err(ParseErr::new(
+                "range bounds should fit in the integer field",
+                start,
+            ));
+        }
+        Ok(Token::Int {
+            start: lower,
+            len: usize::try_from(upper - lower)
+                .ok()
+                .and_then(|len| len.checked_add(1))
+                .ok_or_else(|| ParseErr::new("integer range is too large", start))?,
+            width,
+            big_endian,
+        })
+    }
+    /// Returns the token for a range of dates such as
+    /// "2000-01-01..=2009-12-31:DD/MM/YY", where `start` is the offset of
+    /// the range in the source. The bounds are written as "YYYY-MM-DD", and
+    /// the optional format after the ':' defaults to "YYYYMMDD" (see
+    /// `Date::render`)
+    fn dates(&mut self, arg: &str, start: usize) -> Result<Token, ParseErr> {
+        let (bounds, format) = Self::format(arg);
+        let (lower, upper, inclusive) = Self::range(bounds, start)?;
+        let bound = |s: &str| {
+            Date::parse(s).map(Date::days).ok_or_else(|| {
+                ParseErr::new("date bounds should be valid dates like 'YYYY-MM-DD'", start)
+            })
+        };
+        let (lower, upper) = (bound(lower)?, bound(upper)? - i64::from(!inclusive));
+        if lower > upper {
+            return Err(ParseErr::new("ranges should be nonempty", start));
+        }
+        let format = if format.is_empty() {
+            "YYYYMMDD"
+        } else {
+            format
+        };
+        let mut seen = HashSet::new();
+        let dates = (lower..=upper)
+            .map(|days| Date::from_days(days).render(format))
+            .filter(|date| seen.insert(date.clone()))
+            .collect();
+        Ok(self.members(dates))
+    }
+    /// Returns the token for a window of Unix timestamps such as
+    /// "2020-01-01..2020-01-02:msx", where `start` is the offset of the
+    /// window in the source. The bounds are written like `date::timestamp`
+    /// expects, and the optional format after the last ':' is the unit, 's'
+    /// (the default) or "ms", followed by a radix (see `digits`)
+    fn timestamps(arg: &str, start: usize) -> Result<Token, ParseErr> {
+        // times contain colons too, so the format is after the last one
+        let (bounds, format) = match arg.rfind(':') {
+            Some(i) if !arg[i + 1..].starts_with(|c: char| c.is_ascii_digit()) => {
+                (&arg[..i], &arg[i + 1..])
+            }
+            _ => (arg, ""),
+        };
+        let (lower, upper, inclusive) = Self::range(bounds, start)?;
+        let (unit, radix) = if let Some(radix) = format.strip_prefix("ms") {
+            (1000, radix)
+        } else {
+            (1, format.strip_prefix('s').unwrap_or(format))
+        };
+        let digits = Self::digits(radix, start)?;
+        let bound = |s: &str| {
+            date::timestamp(s)
+                .and_then(|secs| secs.checked_mul(unit))
+                .ok_or_else(|| {
+                    ParseErr::new(
+                        "timestamp bounds should be seconds or dates like 'YYYY-MM-DDTHH:MM:SS'",
+                        start,
+                    )
+                })
+        };
+        let (lower, upper) = (bound(lower)?, bound(upper)? - i64::from(!inclusive));
+        if lower < 0 {
+            return Err(ParseErr::new("timestamps should be after 1970", start));
+        }
+        if lower > upper {
+            return Err(ParseErr::new("ranges should be nonempty", start));
+        }
+        Self::numbers(lower as u64, upper as u64, 0, digits, b' ', start)
+    }
+    /// Returns the offset and contents of an argument in curly braces, such
+    /// as the name in "\k{name}"; `msg` is the error for a missing '{'
+    fn braced(&mut self, msg: &'static str) -> Result<(usize, &'s str), ParseErr> {
+        if self.consume_if(|c| c == '{')?.is_none() {
+            return Err(self.err(msg));
+        }
+        let start = self.idx + 1;
+        while self.consume_char()? != '}' {}
+        Ok((start, &self.src[start..self.idx]))
+    }
+    /// Returns the byte for an escape that can appear both inside and outside
+    /// of character classes, where `c` is the char following the '\\'
+    fn byte_escape(&mut self, c: char) -> Result<u8, ParseErr> {
+        match c {
+            'n' => Ok(b'\n'),
+            'r' => Ok(b'\r'),
+            't' => Ok(b'\t'),
+            '0' => Ok(b'\0'),
+            'x' => {
+                let start = self.idx;
+                let mut byte = 0;
+                for _ in 0..2 {
+                    let digit = self.consume_if(|c| c.is_ascii_hexdigit())?.ok_or_else(|| {
+                        ParseErr::new("'\\x' should be followed by two hex digits", start)
+                    })?;
+                    byte =
+                        byte * 16 + digit.to_digit(16).expect("we checked for a hex digit") as u8;
+                }
+                Ok(byte)
+            }
+            _ => unreachable!(),
+        }
+    }
+    /// Returns a range, open-ended range, or repeat token
+    fn left_curly(&mut self) -> Result<Token, ParseErr> {
+        let start = self.idx;
+        let lower = self.consume_number()?;
+        match self.consume_char()? {
+            ',' => {
+                if self.consume_if(|c| c == '}')?.is_some() {
+                    return Ok(Token::AtLeast(lower));
+                }
+                let upper = self.consume_number()?;
+                if self.consume_char()? == '}' {
+                    use Ordering::*;
+                    match lower.cmp(&upper) {
+                        Less => Ok(Token::Range {lower, upper}),
+                        Equal => Err(ParseErr::new("a range's bounds should not be equal; consider using the repeat syntax", start)),
+                        Greater => Err(ParseErr::new("a range's lower bound should be less the upper bound", start)),
+                    }
+                } else {
+                    Err(self.err("expected closing '}' for range"))
+                }
+            }
+            '}' => {
+                if lower > 1 {
+                    Ok(Token::Repeat(lower))
+                } else {
+                    Err(ParseErr::new(
+                        "number of repeats in a repetition should be greater than 1",
+                        start,
+                    ))
+                }
+            }
+            _ => Err(self.err("expected closing '}' for repeat")),
+        }
+    }
+    /// Returns a group token; alternatives are separated by '|' and may
+    /// contain nested groups. A group starting with "?" and then ':' doesn't
+    /// capture, and any flags between the two change how the group is lexed:
+    /// - 'i' matches its literal characters in either case
+    /// - 'd' makes every class position in the group take a distinct value
+    ///
+    /// Capturing groups are numbered by their '(' from left to right, and
+    /// each alternative is wrapped in markers for the group
+    fn left_paren(&mut self) -> Result<Token, ParseErr> {
+        let fold_case = self.fold_case;
+        let mut capture = None;
+        let mut distinct = false;
+        if self.consume_if(|c| c == '?')?.is_some() {
+            loop {
+                match self.consume_char()? {
+                    ':' => break,
+                    'i' => self.fold_case = true,
+                    'd' => distinct = true,
+                    _ => {
+                        return Err(
+                            self.err("'(?' should be followed by ':' or flags, such as 'i' or 'd'")
+                        )
+                    }
+                }
+            }
+        } else {
+            self.ctx.groups += 1;
+            self.groups.push(self.ctx.groups);
+            self.open.push(self.groups.len());
+            capture = Some(self.ctx.groups);
+        }
+        let alts = self.alternatives();
+        self.fold_case = fold_case;
+        let mut alts = alts?;
+        // alternatives that are all literals of the same length become one
+        // class, so that a range on the group adds one run per count instead
+        // of one per combination of alternatives
+        if let Some(literals) = alts
+            .iter()
+            .map(|alt| Self::literal_bytes(alt))
+            .collect::<Option<Vec<_>>>()
+        {
+            let width = literals[0].len();
+            if literals.len() > 1 && literals.iter().all(|l| l.len() == width) {
+                alts = vec![vec![self.class_with_width(literals.concat().into(), width)]];
+            }
+        }
+        if let Some(id) = capture {
+            self.open.pop();
+            alts = Self::wrap(alts, Token::Open(id), Token::Close(id));
+        }
+        if distinct {
+            alts = Self::wrap(alts, Token::BeginDistinct, Token::EndDistinct);
+        }
+        Ok(Token::Group(alts))
+    }
+    /// Returns the alternatives up to the closing ')'
+    fn alternatives(&mut self) -> Result<Vec<Vec<Token>>, ParseErr> {
+        let mut alts = vec![];
+        let mut alt = vec![];
+        let mut start = self.idx;
+        loop {
+            if let Some(c) = self.consume_if(|c| matches!(c, ')' | '|'))? {
+                Self::validate(&alt, Some(start))?;
+                alts.push(alt.drain(..).map(|(_, t)| t).collect());
+                start = self.idx;
+                if c == ')' {
+                    return Ok(alts);
+                }
+            } else {
+                alt.push(self.next().expect("consume_if checks for end of input")?);
+            }
+        }
+    }
+    /// Returns the bytes of an alternative that only has literal chars in it
+    /// (including the groups that multi-byte chars become)
+    fn literal_bytes(alt: &[Token]) -> Option<Vec<u8>> {
+        let mut bytes = vec![];
+        for t in alt {
+            match t {
+                Token::Char(b) => bytes.push(*b),
+                Token::Group(alts) if alts.len() == 1 => {
+                    bytes.extend(Self::literal_bytes(&alts[0])?)
+                }
+                _ => return None,
+            }
+        }
+        Some(bytes)
+    }
+    /// Puts a pair of markers around every alternative of a group
+    fn wrap(alts: Vec<Vec<Token>>, begin: Token, end: Token) -> Vec<Vec<Token>> {
+        alts.into_iter()
+            .map(|alt| {
+                iter::once(begin.clone())
+                    .chain(alt)
+                    .chain(iter::once(end.clone()))
+                    .collect()
+            })
+            .collect()
+    }
+    /// Returns the next member of a character class, or None if the class
+    /// was closed:
+    /// - "\\" is a literal backslash
+    /// - "\[" is a literal opening bracket
+    /// - "\]" is a literal closing bracket
+    /// - "\-" is a literal dash
+    /// - "\^" is a literal caret
+    /// - "\xNN", "\n", "\r", "\t", and "\0" are byte escapes
+    /// - "-" right before the closing ']' is a literal dash
+    fn class_char(&mut self) -> Result<Option<Member>, ParseErr> {
+        match self.consume_char()? {
+            ']' => Ok(None),
+            '[' => Err(self.err("'[' should be preceded with a '\\' in character classes")),
+            '-' if self.peek2().0 == Some(']') => Ok(Some(Member::Byte(b'-'))),
+            '-' => Err(self.err("'-' should be preceded with a '\\' in character classes")),
+            '\\' => match self.consume_char()? {
+                c @ '\\' | c @ '[' | c @ ']' | c @ '-' | c @ '^' => Ok(Some(Member::Byte(c as u8))),
+                c @ 'x' | c @ 'n' | c @ 'r' | c @ 't' | c @ '0' => {
+                    self.byte_escape(c).map(|b| Some(Member::Byte(b)))
+                }
+                _ => Err(self.err(
+                    "'\\' should be followed by a byte escape, ']', '[', '-', '^', or '\\' in character classes",
+                )),
+            },
+            c if self.opts.bytes || c.is_ascii() => Ok(Some(Member::Byte(c as u8))),
+            c => Ok(Some(Member::Char(c))),
+        }
+    }
+    /// Returns a character class token for the set up to the closing ']'
+    fn left_bracket(&mut self) -> Result<Token, ParseErr> {
+        let set = self.class_set()?;
+        Ok(self.members(set))
+    }
+    /// Returns the next two chars without consuming them
+    fn peek2(&self) -> (Option<char>, Option<char>) {
+        let mut chars = self.chars.clone().map(|(_, c)| c);
+        (chars.next(), chars.next())
+    }
+    /// Returns the members of a character class up to its closing ']'. A
+    /// leading '^' takes the complement of the whole class over printable
+    /// ASCII (or over all bytes in byte mode), and the rest is a sequence of
+    /// sets separated by operators, which are applied from left to right:
+    /// - "||" is the union of two sets
+    /// - "&&" is the intersection of two sets
+    /// - "--" is the first set without the members of the second
+    ///
+    /// Members that a union shares with the first set are dropped, so the
+    /// class is enumerated in the order that its members first appear
+    fn class_set(&mut self) -> Result<Vec<Vec<u8>>, ParseErr> {
+        let start = self.idx;
+        let negated = self.consume_if(|c| c == '^')?.is_some();
+        let mut members = self.class_items()?;
+        while let (Some(op), Some(next)) = self.peek2() {
+            if op != next || !matches!(op, '|' | '&' | '-') {
+                break;
+            }
+            self.consume_char()?;
+            self.consume_char()?;
+            let start = self.idx;
+            let other = self.class_items()?;
+            if members.is_empty() || other.is_empty() {
+                return Err(ParseErr::new(
+                    "class operators should have a nonempty set on each side",
+                    start,
+                ));
+            }
+            match op {
+                '|' => {
+                    let mut seen: HashSet<_> = members.iter().cloned().collect();
+                    members.extend(other.into_iter().filter(|m| seen.insert(m.clone())));
+                }
+                '&' => members.retain(|m| other.contains(m)),
+                _ => members.retain(|m| !other.contains(m)),
+            }
+        }
+        if self.consume_char()? != ']' {
+            return Err(self.err("expected ']'"));
+        }
+        if negated {
+            let complement: &mut dyn Iterator<Item = u8> = if self.opts.bytes {
+                &mut (0..=0xff)
+            } else {
+                &mut PRINTABLE.iter().copied()
+            };
+            // members outside of printable ASCII aren't candidates anyway,
+            // but they're probably a mistake
+            if !self.opts.bytes
+                && members
+                    .iter()
+                    .any(|m| !matches!(m[..], [b] if PRINTABLE.contains(&b)))
+            {
+                self.ctx.warnings.push(ParseErr::new(
+                    "negated class only excludes printable ASCII, so its other members are ignored",
+                    start,
+                ));
+            }
+            members = complement
+                .filter(|&b| !members.contains(&vec![b]))
+                .map(|b| vec![b])
+                .collect();
+        }
+        Ok(members)
+    }
+    /// Returns the members of a set in a character class, which runs until
+    /// an operator or the closing ']'; "x-y" expands to every byte or char
+    /// from x to y (inclusive), "[...]" is a nested class, and "\b", "\d",
+    /// "\A", "\a", "\H", "\h", and "\w" are the same classes as outside of
+    /// brackets; like in regexes, a '-' at the start of the set is a literal.
+    /// Members after the first of their kind are dropped with a warning,
+    /// since writing one twice is probably a mistake
+    fn class_items(&mut self) -> Result<Vec<Vec<u8>>, ParseErr> {
+        let start = self.idx;
+        let mut members = vec![];
+        if let (Some('-'), next) = self.peek2() {
+            if next != Some('-') {
+                self.consume_char()?;
+                members.push(vec![b'-']);
+            }
+        }
+        loop {
+            let predefined: Vec<u8> = match self.peek2() {
+                (None, _) | (Some(']'), _) => break,
+                (Some(a), Some(b)) if a == b && matches!(a, '|' | '&' | '-') => break,
+                (Some('['), _) => {
+                    self.consume_char()?;
+                    members.extend(self.class_set()?);
+                    continue;
+                }
+                (Some('\\'), Some('b')) => (0..=0xff).collect(),
+                (Some('\\'), Some('d')) => DIGITS.to_vec(),
+                (Some('\\'), Some('A')) => UPPERCASE_ALPHABET.to_vec(),
+                (Some('\\'), Some('a')) => LOWERCASE_ALPHABET.to_vec(),
+                (Some('\\'), Some('H')) => UPPERCASE_HEX.to_vec(),
+                (Some('\\'), Some('h')) => LOWERCASE_HEX.to_vec(),
+                (Some('\\'), Some('w')) => ALPHANUMERIC.to_vec(),
+                _ => {
+                    self.class_range(&mut members)?;
+                    continue;
+                }
+            };
+            self.consume_char()?;
+            self.consume_char()?;
+            members.extend(predefined.into_iter().map(|b| vec![b]));
+        }
+        let len = members.len();
+        let mut seen = HashSet::new();
+        members.retain(|member| seen.insert(member.clone()));
+        if members.len() < len {
+            self.ctx.warnings.push(ParseErr::new(
+                "character class has duplicate members",
+                start,
+            ));
+        }
+        Ok(members)
+    }
+    /// Adds a single member or a range of members of a character class
+    fn class_range(&mut self, members: &mut Vec<Vec<u8>>) -> Result<(), ParseErr> {
+        let lower = self
+            .class_char()?
+            .expect("class_items checks for the closing ']'");
+        let start = self.idx;
+        // "x-]" is x and a literal dash, and "x--" starts a difference
+        if matches!(
+            self.peek2(),
+            (Some('-'), Some('-')) | (Some('-'), Some(']'))
+        ) || self.consume_if(|c| c == '-')?.is_none()
+        {
+            members.push(lower.bytes());
+            return Ok(());
+        }
+        let upper = self
+            .class_char()?
+            .ok_or_else(|| self.err("expected upper bound for character range"))?;
+        let reversed = ParseErr::new(
+            "a character range's lower bound should not exceed the upper bound",
+            start,
+        );
+        match (lower, upper) {
+            (Member::Byte(lower), Member::Byte(upper)) => {
+                if lower > upper {
+                    return Err(reversed);
+                }
+                members.extend((lower..=upper).map(|b| vec![b]));
+            }
+            (lower, upper) => {
+                let (lower, upper) = lower.char().zip(upper.char()).ok_or_else(|| {
+                    ParseErr::new(
+                        "a character range should not mix non-ASCII characters with bytes above 0x7F",
+                        start,
+                    )
+                })?;
+                if lower > upper {
+                    return Err(reversed);
+                }
+                members.extend((lower..=upper).map(|c| Member::Char(c).bytes()));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'s, 'd> Iterator for Lexer<'s, 'd> {
+    type Item = Result<(usize, Token), ParseErr>;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.chars.next().map(|(i, c)| {
+            self.idx = i;
+            match c {
+                '\\' => self.backslash(),
+                '{' => self.left_curly(),
+                '[' => self.left_bracket(),
+                '(' => self.left_paren(),
+                ']' => Err(self.err("unexpected ']'")),
+                '}' => Err(self.err("unexpected '}'")),
+                ')' => Err(self.err("unexpected ')'")),
+                '|' => Err(self.err("unexpected '|'; alternatives should be inside a group")),
+                _ => Ok(self.literal(c)),
+            }
+            .map(|t| (i, t))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    fn class(id: usize, len: usize) -> Token {
+        Token::Class { id, len, width: 1 }
+    }
+    fn capture(n: usize, alts: Vec<Vec<Token>>) -> Token {
+        Token::Group(Lexer::wrap(alts, Token::Open(n), Token::Close(n)))
+    }
+    fn ok_parse(src: &str, v: Vec<Token>) {
+        assert_eq!(Lexer::tokenize(src, &Options::default()).unwrap().1, v);
+    }
+    fn ok_parse_bytes(src: &[u8], v: Vec<Token>) {
+        let src: String = src.iter().copied().map(char::from).collect();
+        let opts = Options {
+            bytes: true,
+            ..Options::default()
+        };
+        assert_eq!(Lexer::tokenize(&src, &opts).unwrap().1, v);
+    }
+    fn err_parse(src: &str) {
+        let err = Lexer::tokenize(src, &Options::default()).unwrap_err();
+        eprintln!("error: {}", err.msg);
+        eprintln!("  |");
+        eprintln!("  | {}", src);
+        eprintln!("  | {}^", " ".repeat(err.offset));
+        eprintln!("  |");
+    }
+    #[test]
+    fn misc_errors() {
+        err_parse("");
+        err_parse("]");
+        err_parse("}");
+        err_parse("\\s");
+    }
+    #[test]
+    fn literal() {
+        ok_parse("\x00", vec![Token::Char(0)]);
+        ok_parse("\x7F", vec![Token::Char(127)]);
+        ok_parse(
+            "foo",
+            vec![Token::Char(b'f'), Token::Char(b'o'), Token::Char(b'o')],
+        );
+        ok_parse(
+            r"\H\h\A\a\d",
+            vec![
+                class(1, 16),
+                class(2, 16),
+                class(3, 26),
+                class(4, 26),
+                class(5, 10),
+            ],
+        );
+        ok_parse(
+            r"\[\]\{\}",
+            vec![
+                Token::Char(b'['),
+                Token::Char(b']'),
+                Token::Char(b'{'),
+                Token::Char(b'}'),
+            ],
+        );
+    }
+    #[test]
+    fn class_ids() {
+        ok_parse(
+            r"\d\a\d\A",
+            vec![class(1, 10), class(2, 26), class(1, 10), class(3, 26)],
+        );
+    }
+    #[test]
+    fn character_class() {
+        ok_parse("[foo][bar]", vec![class(1, 2), class(2, 3)]);
+        ok_parse(r"[\\]", vec![class(1, 1)]);
+        ok_parse(r"[\[\]]", vec![class(1, 2)]);
+        err_parse("[");
+        err_parse("[\\q]");
+    }
+    #[test]
+    fn character_range() {
+        ok_parse("[a-f0-9]", vec![class(1, 16)]);
+        ok_parse("[A-Fa-f0-9]", vec![class(1, 22)]);
+        ok_parse(r"[a\-z]", vec![class(1, 3)]);
+        ok_parse(r"[\--\]]", vec![class(1, 49)]);
+        ok_parse("[x-x]", vec![class(1, 1)]);
+        err_parse("[z-a]");
+        ok_parse("[a-]", vec![class(1, 2)]);
+        ok_parse("[-a]", vec![class(1, 2)]);
+        ok_parse("[a-z-]", vec![class(1, 27)]);
+        ok_parse("[-]", vec![class(1, 1)]);
+        ok_parse("[^-]", vec![class(1, 94)]);
+        ok_parse("[-a-c]", vec![class(1, 4)]);
+        err_parse("[a-c-e]");
+        err_parse("[a-]b]");
+    }
+    #[test]
+    fn negated_class() {
+        ok_parse(r#"[^"\\]"#, vec![class(1, 93)]);
+        ok_parse("[^a-z]", vec![class(1, 69)]);
+        ok_parse("[^]", vec![class(1, 95)]);
+        ok_parse("[a^]", vec![class(1, 2)]);
+        assert_eq!(
+            Lexer::tokenize("[^ -/:-~]", &Options::default())
+                .unwrap()
+                .0
+                .dict
+                .into_iter()
+                .next(),
+            Some(((DIGITS.into(), 1), 1))
+        );
+        err_parse("[^");
+        let warnings = |src| {
+            Lexer::tokenize(src, &Options::default())
+                .unwrap()
+                .0
+                .warnings
+        };
+        assert_eq!(
+            warnings("x[^é\t]"),
+            vec![ParseErr::new(
+                "negated class only excludes printable ASCII, so its other members are ignored",
+                1,
+            )]
+        );
+        assert!(warnings("[^a-z]").is_empty());
+    }
+    #[test]
+    fn open_range() {
+        ok_parse(
+            "ab{3,}",
+            vec![Token::Char(b'a'), Token::Char(b'b'), Token::AtLeast(3)],
+        );
+        ok_parse(
+            r"x\d{0,}",
+            vec![Token::Char(b'x'), class(1, 10), Token::AtLeast(0)],
+        );
+        err_parse("(ab){2,}");
+        err_parse("[aé]{2,}");
+        // the errors point at the range
+        let err = |src| Lexer::tokenize(src, &Options::default()).unwrap_err();
+        assert_eq!(
+            err("a{2,}b"),
+            ParseErr::new(
+                "open-ended ranges should only come at the end of the pattern",
+                1
+            )
+        );
+        assert_eq!(
+            err("x(a|b{2,})"),
+            ParseErr::new("open-ended ranges should not be inside groups", 5)
+        );
+        let mut opts = Options::default();
+        opts.defs.insert("tail".into(), r"\d{2,}".into());
+        assert!(Lexer::tokenize(r"a\p{tail}", &opts).is_err());
+        assert_eq!(
+            Lexer::check_def(r"\d{2,}", &opts),
+            Err(ParseErr::new(
+                "open-ended ranges should not be inside definitions",
+                2
+            ))
+        );
+    }
+    #[test]
+    fn class_sets() {
+        // classes keep the order that their members are written in, so the
+        // same members in another order get a static of their own
+        let (ctx, tokens) = Lexer::tokenize("[aab][ba][a-cb][ab]", &Options::default()).unwrap();
+        assert_eq!(
+            tokens,
+            vec![class(1, 2), class(2, 2), class(3, 3), class(1, 2)]
+        );
+        assert_eq!(ctx.dict.len(), 3);
+        assert_eq!(ctx.dict[&(b"ab"[..].into(), 1)], 1);
+        assert_eq!(ctx.dict[&(b"ba"[..].into(), 1)], 2);
+        assert_eq!(
+            ctx.warnings,
+            vec![
+                ParseErr::new("character class has duplicate members", 0),
+                ParseErr::new("character class has duplicate members", 9),
+            ]
+        );
+        // members that set operators bring together aren't written twice
+        let (ctx, tokens) =
+            Lexer::tokenize(r"[\h||\d][\a||[a-c]][\d\h]", &Options::default()).unwrap();
+        assert_eq!(tokens, vec![class(1, 16), class(2, 26), class(1, 16)]);
+        assert_eq!(
+            ctx.warnings,
+            vec![ParseErr::new("character class has duplicate members", 19)]
+        );
+        let mut opts = Options::default();
+        opts.classes.insert("v".into(), "aeiouu".into());
+        assert_eq!(Lexer::check_class("aeiouu", &opts).unwrap().len(), 1);
+        assert!(Lexer::tokenize(r"\k{v}", &opts)
+            .unwrap()
+            .0
+            .warnings
+            .is_empty());
+    }
+    #[test]
+    fn class_algebra() {
+        ok_parse(r"[\w--\d]", vec![class(1, 53)]);
+        ok_parse(r"[\A&&[AEIOU]]", vec![class(1, 5)]);
+        ok_parse(r"[\a||\d]", vec![class(1, 36)]);
+        ok_parse(r"[\h--\dd-f]", vec![class(1, 3)]);
+        ok_parse(r"[\d--[3-6]||x]", vec![class(1, 7)]);
+        ok_parse(r"[^\w--_]", vec![class(1, 33)]);
+        ok_parse(r"[[^\d]&&[a-c]]", vec![class(1, 3)]);
+        ok_parse("[(|)&]", vec![class(1, 4)]);
+        err_parse(r"[\d--]");
+        err_parse(r"[&&\d]");
+        err_parse(r"[\d||[a-c]");
+    }
+    #[test]
+    fn repeat() {
+        ok_parse("a{3}", vec![Token::Char(b'a'), Token::Repeat(3)]);
+        err_parse("a{12,");
+        err_parse("{1}");
+        err_parse("a{0}");
+        err_parse("a{234092348903248032948392342349089}");
+    }
+    #[test]
+    fn range() {
+        ok_parse(
+            "a{3,5}",
+            vec![Token::Char(b'a'), Token::Range { lower: 3, upper: 5 }],
+        );
+        ok_parse(
+            "a{30,50}",
+            vec![
+                Token::Char(b'a'),
+                Token::Range {
+                    lower: 30,
+                    upper: 50,
+                },
+            ],
+        );
+        ok_parse(
+            r"\a{1,10}",
+            vec![
+                class(1, 26),
+                Token::Range {
+                    lower: 1,
+                    upper: 10,
+                },
+            ],
+        );
+        err_parse("a{}");
+        err_parse("a{,}");
+        err_parse("a{12,12}");
+        err_parse("a{3,1}");
+        err_parse("a{1,234092348903248032948392342349089}");
+    }
+    #[test]
+    fn byte_escapes() {
+        ok_parse(
+            r"\x00\xfF\n\r\t\0",
+            vec![
+                Token::Char(0),
+                Token::Char(0xff),
+                Token::Char(b'\n'),
+                Token::Char(b'\r'),
+                Token::Char(b'\t'),
+                Token::Char(0),
+            ],
+        );
+        ok_parse(r"[\x80-\xff]", vec![class(1, 128)]);
+        ok_parse(r"[\n\t\^]", vec![class(1, 3)]);
+        ok_parse(r"\b", vec![class(1, 256)]);
+        err_parse(r"\x");
+        err_parse(r"\x4");
+        err_parse(r"\xg0");
+        err_parse(r"[\x4]");
+    }
+    #[test]
+    fn byte_mode() {
+        ok_parse_bytes(b"\xde\xad", vec![Token::Char(0xde), Token::Char(0xad)]);
+        ok_parse_bytes(b"[^\\x00]", vec![class(1, 255)]);
+        ok_parse_bytes(b"[\xf0-\xff]", vec![class(1, 16)]);
+    }
+    #[test]
+    fn utf8() {
+        ok_parse(
+            "é{2}",
+            vec![
+                Token::Group(vec![vec![Token::Char(0xc3), Token::Char(0xa9)]]),
+                Token::Repeat(2),
+            ],
+        );
+        ok_parse(
+            "[αβγ]",
+            vec![Token::Class {
+                id: 1,
+                len: 3,
+                width: 2,
+            }],
+        );
+        ok_parse(
+            "[α-ω]",
+            vec![Token::Class {
+                id: 1,
+                len: 25,
+                width: 2,
+            }],
+        );
+        ok_parse(
+            "[a漢é\\xffb]",
+            vec![Token::Words {
+                id: 1,
+                len: 5,
+                width: 1,
+                widest: 3,
+            }],
+        );
+        ok_parse(
+            "[a-é]",
+            vec![Token::Words {
+                id: 1,
+                len: 137,
+                width: 1,
+                widest: 2,
+            }],
+        );
+        err_parse("[\\x80-é]");
+        err_parse("[é-a]");
+    }
+    #[test]
+    fn named_class() {
+        let mut opts = Options::default();
+        opts.classes.insert("v".into(), "aeiou".into());
+        opts.classes.insert("hex".into(), "0-9a-f".into());
+        opts.classes.insert("not_v".into(), "^aeiou".into());
+        let (ctx, tokens) = Lexer::tokenize(r"\k{v}\h\k{hex}\k{not_v}\k{v}", &opts).unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                class(1, 5),
+                class(2, 16),
+                class(2, 16),
+                class(3, 90),
+                class(1, 5)
+            ]
+        );
+        assert_eq!(ctx.dict.len(), 3);
+        assert!(Lexer::tokenize(r"\k{w}", &opts).is_err());
+        assert!(Lexer::tokenize(r"\k", &opts).is_err());
+        assert!(Lexer::tokenize(r"\k{v", &opts).is_err());
+        assert!(Lexer::check_class("a-z", &opts).is_ok());
+        assert!(Lexer::check_class("z-a", &opts).is_err());
+        assert!(Lexer::check_class("a]b", &opts).is_err());
+        assert!(Lexer::check_class("a[", &opts).is_err());
+    }
+    #[test]
+    fn wordlist() {
+        // a directory of its own, so that concurrent test runs don't race
+        let dir = std::env::temp_dir().join(format!("bonk-wordlist-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("words.txt"), "foo\r\nbar\n\nhello\nbaz\nfoo\n").unwrap();
+        fs::write(dir.join("empty.txt"), "\n\n").unwrap();
+        let opts = Options {
+            base_dir: dir.clone(),
+            ..Options::default()
+        };
+        let words = Lexer::tokenize(r"\f{words.txt}\d{2}", &opts);
+        let empty = Lexer::tokenize(r"\f{empty.txt}", &opts);
+        fs::remove_dir_all(&dir).unwrap();
+        let (ctx, tokens) = words.unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Words {
+                    id: 1,
+                    len: 4,
+                    width: 3,
+                    widest: 5,
+                },
+                class(1, 10),
+                Token::Repeat(2),
+            ]
+        );
+        assert_eq!(ctx.files.len(), 1);
+        // the words keep the order of the file
+        assert_eq!(
+            ctx.words[&(b"foobarhellobaz".to_vec(), vec![0, 3, 6, 11, 14])],
+            1
+        );
+        assert_eq!(
+            ctx.warnings,
+            vec![ParseErr::new("wordlist has duplicate words", 3)]
+        );
+        assert!(empty.is_err());
+        assert!(Lexer::tokenize(r"\f{missing.txt}", &opts).is_err());
+        assert!(Lexer::tokenize(r"\f", &opts).is_err());
+    }
+    #[test]
+    fn case_insensitive() {
+        ok_parse(
+            "(?i:aB1)c",
+            vec![
+                Token::Group(vec![vec![class(1, 2), class(2, 2), Token::Char(b'1')]]),
+                Token::Char(b'c'),
+            ],
+        );
+        ok_parse(
+            r"(?i:x(y|\?))",
+            vec![Token::Group(vec![vec![
+                class(1, 2),
+                capture(1, vec![vec![class(2, 2)], vec![Token::Char(b'?')]]),
+            ]])],
+        );
+        ok_parse(
+            "(?i:é)",
+            vec![Token::Group(vec![vec![Token::Class {
+                id: 1,
+                len: 2,
+                width: 2,
+            }]])],
+        );
+        ok_parse(
+            "(?i:ß)",
+            vec![Token::Group(vec![vec![Token::Group(vec![vec![
+                Token::Char(0xc3),
+                Token::Char(0x9f),
+            ]])]])],
+        );
+        ok_parse_bytes(
+            b"(?i:\xe9)",
+            vec![Token::Group(vec![vec![Token::Char(0xe9)]])],
+        );
+        err_parse("(?");
+        err_parse("(?x:a)");
+        err_parse("(?i");
+        err_parse("(?i)");
+    }
+    #[test]
+    fn leetspeak() {
+        ok_parse(
+            r"\l{pass}\d",
+            vec![
+                Token::Group(vec![vec![
+                    class(1, 2),
+                    class(2, 4),
+                    class(3, 4),
+                    class(3, 4),
+                ]]),
+                class(4, 10),
+            ],
+        );
+        ok_parse(
+            r"\l{é.}",
+            vec![Token::Group(vec![vec![
+                Token::Class {
+                    id: 1,
+                    len: 2,
+                    width: 2,
+                },
+                Token::Char(b'.'),
+            ]])],
+        );
+        let mut opts = Options::default();
+        opts.leet.insert('p', "pP9".into());
+        opts.leet.insert('s', "s$".into());
+        let (ctx, tokens) = Lexer::tokenize(r"\l{PaSs}", &opts).unwrap();
+        assert_eq!(
+            tokens,
+            vec![Token::Group(vec![vec![
+                class(1, 3),
+                class(2, 4),
+                class(3, 2),
+                class(3, 2),
+            ]])]
+        );
+        assert!(ctx.dict.contains_key(&(b"aA4@"[..].into(), 1)));
+        err_parse(r"\l{}");
+        err_parse(r"\l");
+    }
+    #[test]
+    fn group() {
+        ok_parse(
+            "(ab|c)",
+            vec![capture(
+                1,
+                vec![
+                    vec![Token::Char(b'a'), Token::Char(b'b')],
+                    vec![Token::Char(b'c')],
+                ],
+            )],
+        );
+        ok_parse(
+            r"x(a|(b|\d{2}))y",
+            vec![
+                Token::Char(b'x'),
+                capture(
+                    1,
+                    vec![
+                        vec![Token::Char(b'a')],
+                        vec![capture(
+                            2,
+                            vec![
+                                vec![Token::Char(b'b')],
+                                vec![class(1, 10), Token::Repeat(2)],
+                            ],
+                        )],
+                    ],
+                ),
+                Token::Char(b'y'),
+            ],
+        );
+        ok_parse(
+            r"\(\|\)",
+            vec![Token::Char(b'('), Token::Char(b'|'), Token::Char(b')')],
+        );
+        ok_parse("[(|)]", vec![class(1, 3)]);
+        // literal alternatives of the same length become a class
+        ok_parse(
+            "(ab|cd)",
+            vec![capture(
+                1,
+                vec![vec![Token::Class {
+                    id: 1,
+                    len: 2,
+                    width: 2,
+                }]],
+            )],
+        );
+        ok_parse(
+            "(?:a|b|é)",
+            vec![Token::Group(vec![
+                vec![Token::Char(b'a')],
+                vec![Token::Char(b'b')],
+                vec![Token::Group(vec![vec![
+                    Token::Char(0xc3),
+                    Token::Char(0xa9),
+                ]])],
+            ])],
+        );
+        ok_parse(
+            "(?:ü|é)",
+            vec![Token::Group(vec![vec![Token::Class {
+                id: 1,
+                len: 2,
+                width: 2,
+            }]])],
+        );
+        err_parse("(");
+        err_parse("(a");
+        err_parse("a)");
+        err_parse("a|b");
+        err_parse("()");
+        err_parse("(a|)");
+        err_parse("(|a)");
+        err_parse("({2})");
+    }
+    #[test]
+    fn group_quantifier() {
+        ok_parse(
+            r"(ab\d){2,4}",
+            vec![
+                capture(
+                    1,
+                    vec![vec![Token::Char(b'a'), Token::Char(b'b'), class(1, 10)]],
+                ),
+                Token::Range { lower: 2, upper: 4 },
+            ],
+        );
+        ok_parse(
+            r"(\d{2}-){3}",
+            vec![
+                capture(
+                    1,
+                    vec![vec![class(1, 10), Token::Repeat(2), Token::Char(b'-')]],
+                ),
+                Token::Repeat(3),
+            ],
+        );
+        err_parse("(a){2}{3}");
+        err_parse("(a{2}{3})");
+    }
+    #[test]
+    fn backreference() {
+        ok_parse(
+            r"(a|\d)-\1",
+            vec![
+                capture(1, vec![vec![Token::Char(b'a')], vec![class(1, 10)]]),
+                Token::Char(b'-'),
+                Token::Backref(1),
+            ],
+        );
+        ok_parse(
+            r"(?:x)(y)\1{2}\0",
+            vec![
+                Token::Group(vec![vec![Token::Char(b'x')]]),
+                capture(1, vec![vec![Token::Char(b'y')]]),
+                Token::Backref(1),
+                Token::Repeat(2),
+                Token::Char(0),
+            ],
+        );
+        ok_parse(
+            r"((a)\2)\1",
+            vec![
+                capture(
+                    1,
+                    vec![vec![
+                        capture(2, vec![vec![Token::Char(b'a')]]),
+                        Token::Backref(2),
+                    ]],
+                ),
+                Token::Backref(1),
+            ],
+        );
+        err_parse(r"\1");
+        err_parse(r"(a)\2");
+        err_parse(r"(a\1)");
+        err_parse(r"(?:a)\1");
+        err_parse(r"\1{2}");
+    }
+    #[test]
+    fn anagram() {
+        ok_parse(
+            r"\m{abca}\d",
+            vec![
+                Token::Anagram {
+                    id: 1,
+                    width: 1,
+                    counts: vec![2, 1, 1],
+                },
+                class(2, 10),
+            ],
+        );
+        ok_parse(
+            r"\m{éèé}{2}",
+            vec![
+                Token::Anagram {
+                    id: 1,
+                    width: 2,
+                    counts: vec![2, 1],
+                },
+                Token::Repeat(2),
+            ],
+        );
+        ok_parse_bytes(
+            b"\\m{\xe9a}",
+            vec![Token::Anagram {
+                id: 1,
+                width: 1,
+                counts: vec![1, 1],
+            }],
+        );
+        err_parse(r"\m");
+        err_parse(r"\m{}");
+        err_parse(r"\m{aé}");
+        err_parse(r"\m{ab");
+    }
+    #[test]
+    fn distinct() {
+        ok_parse(
+            r"(?d:\a{2}|x)",
+            vec![Token::Group(vec![
+                vec![
+                    Token::BeginDistinct,
+                    class(1, 26),
+                    Token::Repeat(2),
+                    Token::EndDistinct,
+                ],
+                vec![Token::BeginDistinct, Token::Char(b'x'), Token::EndDistinct],
+            ])],
+        );
+        ok_parse(
+            "(?di:a)",
+            vec![Token::Group(vec![vec![
+                Token::BeginDistinct,
+                class(1, 2),
+                Token::EndDistinct,
+            ]])],
+        );
+        err_parse("(?d)");
+        err_parse("(?dx:a)");
+    }
+    #[test]
+    fn number() {
+        let number = |start, len, width, digits, pad| Token::Number {
+            start,
+            len,
+            width,
+            digits,
+            pad,
+        };
+        ok_parse(
+            r"\i{1024..65536}",
+            vec![Token::Group(vec![
+                vec![number(1024, 8976, 4, DIGITS, b' ')],
+                vec![number(10000, 55536, 5, DIGITS, b' ')],
+            ])],
+        );
+        ok_parse(r"\i{0..=9999:04}", vec![number(0, 10000, 4, DIGITS, b'0')]);
+        ok_parse(
+            r"\i{250..=260:X}",
+            vec![Token::Group(vec![
+                vec![number(250, 6, 2, UPPERCASE_HEX, b' ')],
+                vec![number(256, 5, 3, UPPERCASE_HEX, b' ')],
+            ])],
+        );
+        ok_parse(
+            r"\i{0..8:3o}{2}",
+            vec![number(0, 8, 3, &DIGITS[..8], b' '), Token::Repeat(2)],
+        );
+        ok_parse(
+            r"\i{0..=18446744073709551615:b}",
+            vec![Token::Group(
+                (0..64)
+                    .map(|i| match i {
+                        0 => vec![number(0, 2, 1, &DIGITS[..2], b' ')],
+                        _ => vec![number(1 << i, 1 << i, i + 1, &DIGITS[..2], b' ')],
+                    })
+                    .collect(),
+            )],
+        );
+        err_parse(r"\i");
+        err_parse(r"\i{}");
+        err_parse(r"\i{5..5}");
+        err_parse(r"\i{9..=1}");
+        err_parse(r"\i{a..b}");
+        err_parse(r"\i{1-2}");
+        err_parse(r"\i{1..2:q}");
+        err_parse(r"\i{1..2:x4}");
+    }
+    #[test]
+    fn date() {
+        let wide = |id, len, width| Token::Class { id, len, width };
+        ok_parse(r"\D{2024-02-27..=2024-03-01}", vec![wide(1, 4, 8)]);
+        ok_parse(r"\D{2023-02-27..2023-03-01:DD/MM}", vec![wide(1, 2, 5)]);
+        ok_parse(
+            r"\D{2024-09-30..=2024-10-10:D}",
+            vec![Token::Words {
+                id: 1,
+                len: 11,
+                width: 1,
+                widest: 2,
+            }],
+        );
+        ok_parse(r"\D{2023-01-01..=2024-12-31:MMDD}", vec![wide(1, 366, 4)]);
+        err_parse(r"\D{2023-02-29..2023-03-01}");
+        err_parse(r"\D{2024-01-02..2024-01-01}");
+        err_parse(r"\D{2024-01-01}");
+        err_parse(r"\D{2024-1-1..2024-2-1}");
+    }
+    #[test]
+    fn timestamp() {
+        let number = |start, len, width, digits| Token::Number {
+            start,
+            len,
+            width,
+            digits,
+            pad: b' ',
+        };
+        ok_parse(
+            r"\T{1970-01-01T00:00:05..=1970-01-01T00:00:10}",
+            vec![Token::Group(vec![
+                vec![number(5, 5, 1, DIGITS)],
+                vec![number(10, 1, 2, DIGITS)],
+            ])],
+        );
+        ok_parse(
+            r"\T{1000000000..1000000005:x}",
+            vec![number(1_000_000_000, 5, 8, LOWERCASE_HEX)],
+        );
+        ok_parse(
+            r"\T{2001-09-09T01:46:40..=2001-09-09T01:46:40:ms}",
+            vec![number(1_000_000_000_000, 1, 13, DIGITS)],
+        );
+        ok_parse(
+            r"\T{2001-09-09..2001-09-10:msX}",
+            vec![number(999_993_600_000, 86_400_000, 10, UPPERCASE_HEX)],
+        );
+        err_parse(r"\T{1969-12-31..1970-01-02}");
+        err_parse(r"\T{5..5}");
+        err_parse(r"\T{0..5:us}");
+        err_parse(r"\T{2001-09-09T25:00:00..2001-09-10}");
+    }
+    #[test]
+    fn int() {
+        let int = |start, len, width, big_endian| Token::Int {
+            start,
+            len,
+            width,
+            big_endian,
+        };
+        ok_parse(
+            r"a\u32le{0..100000}",
+            vec![Token::Char(b'a'), int(0, 100_000, 4, false)],
+        );
+        ok_parse(r"\u16be{0..=65535}", vec![int(0, 65536, 2, true)]);
+        ok_parse(
+            r"\u64le{18446744073709551614..=18446744073709551615}{2}",
+            vec![int(u64::MAX - 1, 2, 8, false), Token::Repeat(2)],
+        );
+        err_parse(r"\u8le{0..1}");
+        err_parse(r"\u32{0..1}");
+        err_parse(r"\u32ne{0..1}");
+        err_parse(r"\u16le{0..=65536}");
+        err_parse(r"\u64be{0..=18446744073709551615}");
+        err_parse(r"\u32le{2..1}");
+    }
+    #[test]
+    fn def() {
+        let mut opts = Options::default();
+        for &(name, body) in &[
+            ("oct", r"\d{1,3}"),
+            ("pair", r"(\a)\1"),
+            ("rec", r"x\p{rec}"),
+            ("a", r"\p{b}"),
+            ("b", r"(\p{a})"),
+            ("empty", r"(?:)"),
+        ] {
+            opts.defs.insert(name.into(), body.into());
+        }
+        let oct = Token::Group(vec![vec![
+            class(1, 10),
+            Token::Range { lower: 1, upper: 3 },
+        ]]);
+        assert_eq!(
+            Lexer::tokenize(r"\p{oct}.\p{oct}", &opts).unwrap().1,
+            vec![oct.clone(), Token::Char(b'.'), oct]
+        );
+        assert_eq!(
+            Lexer::tokenize(r"(x)\p{pair}\1", &opts).unwrap().1,
+            vec![
+                capture(1, vec![vec![Token::Char(b'x')]]),
+                Token::Group(vec![vec![
+                    capture(2, vec![vec![class(1, 26)]]),
+                    Token::Backref(2),
+                ]]),
+                Token::Backref(1),
+            ]
+        );
+        for src in &[
+            r"\p{nope}",
+            r"\p{rec}",
+            r"\p{a}",
+            r"\p{oct",
+            r"\p{empty}",
+            r"\2",
+        ] {
+            assert!(Lexer::tokenize(src, &opts).is_err());
+        }
+        assert!(Lexer::check_def(r"\p{oct}\p{pair}", &opts).is_ok());
+        assert!(Lexer::check_def(r"\p{a}", &opts).is_err());
+    }
+}
@@ -0,0 +1,156 @@
+//! The pattern language behind `bonk!`, which can also compile patterns at
+//! runtime, such as ones read from user input or config files.
+
+mod date;
+mod lexer;
+mod mask;
+mod parser;
+
+pub use lexer::{Context, Dict, Lexer, Options, ParseErr, Token, Words};
+pub use mask::Mask;
+pub use parser::{
+    Change, Compliance, Final, Growth, Init, Limits, Partition, Policy, Rule, Run, Shift, Values,
+    Write,
+};
+
+use std::borrow::Cow;
+use std::time::Duration;
+
+/// A compiled pattern: the runs that make up its candidates, in the order
+/// that they're tried, and the classes that they draw from
+#[derive(Clone, PartialEq, Debug)]
+pub struct Pattern {
+    result: Final,
+    /// The members of each class, indexed by id
+    classes: Vec<Cow<'static, [u8]>>,
+    /// The members of each set of words, laid out back to back, and the
+    /// offsets that they start at, indexed by id
+    words: Vec<(Vec<u8>, Vec<usize>)>,
+}
+
+impl Pattern {
+    /// Compiles a pattern with the default options and no limits
+    pub fn compile(src: &str) -> Result<Self, ParseErr> {
+        Self::with_options(src, &Options::default(), &Limits::default())
+    }
+    pub fn with_options(src: &str, opts: &Options, limits: &Limits) -> Result<Self, ParseErr> {
+        let (ctx, tokens) = Lexer::tokenize(src, opts)?;
+        Self::from_tokens(ctx, &tokens, limits)
+    }
+    /// Compiles hashcat masks, one per line like an .hcmask file
+    pub fn mask(src: &str, limits: &Limits) -> Result<Self, ParseErr> {
+        let (ctx, tokens) = Mask::tokenize(src)?;
+        Self::from_tokens(ctx, &tokens, limits)
+    }
+    fn from_tokens(ctx: Context, tokens: &[Token], limits: &Limits) -> Result<Self, ParseErr> {
+        let result = Final::from_tokens(ctx, tokens, limits, 1, Partition::None)?;
+        // the policy's tables are built for runs whose length is known
+        if result.growth.is_some() && result.policy.is_some() {
+            return Err(ParseErr::new(
+                "policies can't be combined with open-ended ranges",
+                0,
+            ));
+        }
+        let mut classes = vec![Cow::Borrowed(&[][..]); result.statics.len() + 1];
+        for ((class, _), &id) in &result.statics {
+            classes[id] = class.clone();
+        }
+        let mut words = vec![Default::default(); result.words.len() + 1];
+        for (members, &id) in &result.words {
+            words[id] = members.clone();
+        }
+        Ok(Self {
+            result,
+            classes,
+            words,
+        })
+    }
+    /// Returns the runs, which may be empty if no candidates fit the limits
+    pub fn runs(&self) -> &[Run] {
+        &self.result.tasks[0]
+    }
+    /// Splits the runs into `n` tasks of about the same size, one for each
+    /// thread, by dividing up the values of each run's first change, or the
+    /// first member that an open-ended range adds to a run without changes
+    pub fn split(&self, n: usize) -> Vec<Vec<Run>> {
+        self.result.weak_partition(self.runs().to_vec(), n)
+    }
+    /// Returns how long enumerating the pattern can take before it stops
+    /// early, if there's a limit
+    pub fn timeout(&self) -> Option<Duration> {
+        self.result.timeout
+    }
+    /// Returns the members of the class with this id, laid out back to back
+    pub fn class(&self, id: usize) -> &[u8] {
+        &self.classes[id]
+    }
+    /// Returns the members of the words with this id, laid out back to back,
+    /// and the offsets that each one starts at
+    pub fn words(&self, id: usize) -> (&[u8], &[usize]) {
+        let (members, offsets) = &self.words[id];
+        (members, offsets)
+    }
+    pub fn policy(&self) -> Option<&Policy> {
+        self.result.policy.as_ref()
+    }
+    pub fn growth(&self) -> Option<&Growth> {
+        self.result.growth.as_ref()
+    }
+    /// Returns the length of the longest candidate, before any growth
+    pub fn max_len(&self) -> usize {
+        self.result.max_size
+    }
+    /// Returns the number of candidates, saturating at u128::MAX
+    pub fn keyspace(&self) -> u128 {
+        self.result
+            .keyspace
+            .iter()
+            .fold(0, |a, &b| u128::saturating_add(a, b))
+    }
+    /// Returns problems that didn't stop the pattern from compiling
+    pub fn warnings(&self) -> &[ParseErr] {
+        &self.result.warnings
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn compile() {
+        let pattern = Pattern::compile(r"[ab]\d(x|yz)").unwrap();
+        assert_eq!(pattern.runs().len(), 2);
+        assert_eq!(pattern.max_len(), 4);
+        assert_eq!(pattern.keyspace(), 40);
+        assert_eq!(pattern.class(1), b"ab");
+        assert_eq!(pattern.split(3).iter().map(Vec::len).sum::<usize>(), 6);
+        assert_eq!(
+            Pattern::compile("[aa]").unwrap().warnings(),
+            &[ParseErr::new("character class has duplicate members", 0)]
+        );
+        assert_eq!(
+            Pattern::compile("(a").unwrap_err(),
+            ParseErr::new("unexpected end of input", 1)
+        );
+        let limits = Limits {
+            length: Some(2..=3),
+            ..Limits::default()
+        };
+        assert_eq!(
+            Pattern::with_options("a{0,5}", &Options::default(), &limits)
+                .unwrap()
+                .keyspace(),
+            2
+        );
+        assert_eq!(Pattern::mask("?d?d", &limits).unwrap().keyspace(), 100);
+        let limits = Limits {
+            policy: vec![Rule {
+                members: b"a".to_vec(),
+                min: 1,
+                max: None,
+            }],
+            ..Limits::default()
+        };
+        assert!(Pattern::with_options("a{1,}", &Options::default(), &limits).is_err());
+    }
+}
@@ -0,0 +1,217 @@
+use super::lexer::{
+    intern, Context, Dict, DIGITS, LOWERCASE_ALPHABET, LOWERCASE_HEX, UPPERCASE_ALPHABET,
+    UPPERCASE_HEX,
+};
+use super::{ParseErr, Token};
+
+static SPECIAL: &[u8] = b" !\"#$%&'()*+,-./:;<=>?@[\\]^_`{|}~";
+
+/// Translates hashcat masks into tokens; the source is read like an .hcmask
+/// file, so each line is one mask of the form `[?1,][?2,][?3,][?4,]mask`
+pub struct Mask<'d> {
+    dict: &'d mut Dict,
+    charsets: Vec<Vec<u8>>,
+}
+
+impl<'d> Mask<'d> {
+    /// Returns the tokens for every mask in `src`; if there are several, they
+    /// become the alternatives of a group so that they run in order
+    pub fn tokenize(src: &str) -> Result<(Context, Vec<Token>), ParseErr> {
+        let mut ctx = Context::default();
+        let mut masks = vec![];
+        let mut start = 0;
+        for line in src.split('\n') {
+            let trimmed = line.strip_suffix('\r').unwrap_or(line);
+            if !trimmed.is_empty() && !trimmed.starts_with('#') {
+                let mut mask = Mask {
+                    dict: &mut ctx.dict,
+                    charsets: vec![],
+                };
+                masks.push(mask.line(trimmed.as_bytes(), start)?);
+            }
+            start += line.len() + 1;
+        }
+        let tokens = match masks.len() {
+            0 => return Err(ParseErr::new("mask should be nonempty", 0)),
+            1 => masks.pop().expect("we just checked the length"),
+            _ => vec![Token::Group(masks)],
+        };
+        Ok((ctx, tokens))
+    }
+    /// Returns the tokens for a single mask, where `start` is the offset of
+    /// the line in the source
+    fn line(&mut self, line: &[u8], start: usize) -> Result<Vec<Token>, ParseErr> {
+        let mut fields = Self::fields(line, start);
+        let mask = fields
+            .pop()
+            .expect("split always returns at least one field");
+        if fields.len() > 4 {
+            return Err(ParseErr::new(
+                "a mask should have at most four custom charsets",
+                fields[4].first().map_or(start, |&(i, _)| i),
+            ));
+        }
+        for field in &fields {
+            if field.is_empty() {
+                return Err(ParseErr::new("custom charsets should be nonempty", start));
+            }
+            let mut charset = vec![];
+            for class in self.placeholders(field)? {
+                for b in class {
+                    if !charset.contains(&b) {
+                        charset.push(b);
+                    }
+                }
+            }
+            self.charsets.push(charset);
+        }
+        if mask.is_empty() {
+            return Err(ParseErr::new("mask should be nonempty", start));
+        }
+        Ok(self
+            .placeholders(&mask)?
+            .into_iter()
+            .map(|class| match class.as_slice() {
+                &[b] => Token::Char(b),
+                _ => intern(self.dict, class.into(), 1),
+            })
+            .collect())
+    }
+    /// Splits a line on commas that aren't escaped with a '\', pairing each
+    /// byte with its offset in the source
+    fn fields(line: &[u8], start: usize) -> Vec<Vec<(usize, u8)>> {
+        let mut fields = vec![vec![]];
+        let mut bytes = line.iter().copied().enumerate().peekable();
+        while let Some((i, b)) = bytes.next() {
+            match b {
+                b'\\' if bytes.peek().map(|&(_, b)| b) == Some(b',') => {
+                    let (i, b) = bytes.next().expect("we just peeked");
+                    fields.last_mut().unwrap().push((start + i, b));
+                }
+                b',' => fields.push(vec![]),
+                _ => fields.last_mut().unwrap().push((start + i, b)),
+            }
+        }
+        fields
+    }
+    /// Expands each placeholder in a field into the bytes it stands for;
+    /// literal bytes stand for themselves
+    /// - "?l" is [a-z]
+    /// - "?u" is [A-Z]
+    /// - "?d" is [0-9]
+    /// - "?h" is [0-9a-f]
+    /// - "?H" is [0-9A-F]
+    /// - "?s" is every printable ASCII symbol, including space
+    /// - "?a" is ?l?u?d?s
+    /// - "?b" is every byte from 0x00 to 0xFF
+    /// - "?1" through "?4" are the custom charsets
+    /// - "??" is a literal question mark
+    fn placeholders(&self, field: &[(usize, u8)]) -> Result<Vec<Vec<u8>>, ParseErr> {
+        let mut classes = vec![];
+        let mut bytes = field.iter().copied();
+        while let Some((i, b)) = bytes.next() {
+            if b != b'?' {
+                classes.push(vec![b]);
+                continue;
+            }
+            let (_, name) = bytes
+                .next()
+                .ok_or_else(|| ParseErr::new("'?' should be followed by a charset name", i))?;
+            classes.push(match name {
+                b'l' => LOWERCASE_ALPHABET.to_vec(),
+                b'u' => UPPERCASE_ALPHABET.to_vec(),
+                b'd' => DIGITS.to_vec(),
+                b'h' => LOWERCASE_HEX.to_vec(),
+                b'H' => UPPERCASE_HEX.to_vec(),
+                b's' => SPECIAL.to_vec(),
+                b'a' => [LOWERCASE_ALPHABET, UPPERCASE_ALPHABET, DIGITS, SPECIAL].concat(),
+                b'b' => (0..=0xff).collect(),
+                b'?' => vec![b'?'],
+                b'1'..=b'4' => self
+                    .charsets
+                    .get(usize::from(name - b'1'))
+                    .cloned()
+                    .ok_or_else(|| ParseErr::new("custom charset is not defined", i))?,
+                _ => {
+                    return Err(ParseErr::new(
+                        "'?' should be followed by 'l', 'u', 'd', 'h', 'H', 's', 'a', 'b', '1' through '4', or '?'",
+                        i,
+                    ))
+                }
+            });
+        }
+        Ok(classes)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    fn class(id: usize, len: usize) -> Token {
+        Token::Class { id, len, width: 1 }
+    }
+    fn ok_parse(src: &str, v: Vec<Token>) {
+        assert_eq!(Mask::tokenize(src).unwrap().1, v);
+    }
+    fn err_parse(src: &str) {
+        let err = Mask::tokenize(src).unwrap_err();
+        eprintln!("error: {}", err.msg);
+        eprintln!("  |");
+        eprintln!("  | {}", src);
+        eprintln!("  | {}^", " ".repeat(err.offset));
+        eprintln!("  |");
+    }
+    #[test]
+    fn builtin() {
+        ok_parse(
+            "?u?l?d?d",
+            vec![class(1, 26), class(2, 26), class(3, 10), class(3, 10)],
+        );
+        ok_parse(
+            "?s?a?b?h?H",
+            vec![
+                class(1, 33),
+                class(2, 95),
+                class(3, 256),
+                class(4, 16),
+                class(5, 16),
+            ],
+        );
+        ok_parse(
+            "a??b",
+            vec![Token::Char(b'a'), Token::Char(b'?'), Token::Char(b'b')],
+        );
+        err_parse("");
+        err_parse("?");
+        err_parse("?x");
+    }
+    #[test]
+    fn custom_charsets() {
+        ok_parse("?l?d,?1?1", vec![class(1, 36), class(1, 36)]);
+        ok_parse("abc,?d,?1?2", vec![class(1, 3), class(2, 10)]);
+        ok_parse("?d?d,?1", vec![class(1, 10)]);
+        ok_parse("?d,?1x,?2", vec![class(1, 11)]);
+        ok_parse(r"\,-,?1", vec![class(1, 2)]);
+        ok_parse(
+            r"a\,b",
+            vec![Token::Char(b'a'), Token::Char(b','), Token::Char(b'b')],
+        );
+        err_parse("?1");
+        err_parse("?d,?2");
+        err_parse(",?1");
+        err_parse("?d,");
+        err_parse("a,b,c,d,e,?1");
+    }
+    #[test]
+    fn file() {
+        ok_parse(
+            "# comment\n?d\r\n\n?l,?1?1\n",
+            vec![Token::Group(vec![
+                vec![class(1, 10)],
+                vec![class(2, 26), class(2, 26)],
+            ])],
+        );
+        err_parse("# comment\n\n");
+        err_parse("?d\n?x");
+    }
+}
@@ -0,0 +1,1879 @@
+use super::lexer::intern;
+use super::{Context, Dict, Lexer, Options, ParseErr, Token, Words};
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::iter::repeat_n;
+use std::ops::{Range, RangeInclusive};
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// The most runs that a pattern can expand to, since each one becomes its own
+/// block of code
+pub const MAX_RUNS: u128 = 1 << 16;
+
+/// The longest that an open-ended range makes candidates by default when the
+/// length limit doesn't have an upper bound
+pub const MAX_GROWN_LEN: usize = 256;
+
+/// The most values that a number or integer range can have if its bytes count
+/// towards the policy, since each of them is checked on its own
+pub const MAX_COUNTED: usize = 1 << 16;
+
+pub enum Partition {
+    Naive,
+    None,
+}
+
+/// Settings that change which runs are generated, and in what order
+pub struct Limits {
+    /// The lengths that candidates are allowed to have
+    pub length: Option<RangeInclusive<usize>>,
+    /// Whether runs are sorted by length, shortest first; otherwise they're
+    /// in the order that the pattern expands to
+    pub shortest_first: bool,
+    /// Rules that every candidate has to follow
+    pub policy: Vec<Rule>,
+    /// The longest that an open-ended range makes candidates when `length`
+    /// doesn't have an upper bound, so that enumerating it stops
+    pub max_grown_len: usize,
+    /// How long enumerating can take before it stops early, if it can take
+    /// as long as it needs otherwise
+    pub timeout: Option<Duration>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            length: None,
+            shortest_first: false,
+            policy: vec![],
+            max_grown_len: MAX_GROWN_LEN,
+            timeout: None,
+        }
+    }
+}
+
+/// A rule in a password policy: candidates should have at least `min` and
+/// at most `max` bytes from `members`
+#[derive(Clone, PartialEq, Debug)]
+pub struct Rule {
+    pub members: Vec<u8>,
+    pub min: usize,
+    pub max: Option<usize>,
+}
+
+/// The states that a password policy compiles to; each state holds a count
+/// for every rule, which saturates one past the rule's maximum (or at its
+/// minimum if it doesn't have one)
+#[derive(Clone, PartialEq, Debug)]
+pub struct Policy {
+    /// The bitmask of the rules that each byte counts towards
+    pub masks: Vec<u8>,
+    /// The state after counting a byte, for each state and bitmask
+    pub next: Vec<Vec<usize>>,
+    /// Whether each state follows every rule
+    pub accept: Vec<bool>,
+}
+
+impl Policy {
+    fn new(rules: &[Rule]) -> Self {
+        let caps: Vec<_> = rules
+            .iter()
+            .map(|rule| rule.max.map_or(rule.min, |max| max + 1))
+            .collect();
+        // the count for the first rule is the least significant digit
+        let decode = |mut state: usize| -> Vec<usize> {
+            caps.iter()
+                .map(|cap| {
+                    let count = state % (cap + 1);
+                    state /= cap + 1;
+                    count
+                })
+                .collect()
+        };
+        let encode = |counts: &[usize]| {
+            counts
+                .iter()
+                .zip(&caps)
+                .rev()
+                .fold(0, |state, (count, cap)| state * (cap + 1) + count)
+        };
+        let num_states = caps.iter().map(|cap| cap + 1).product();
+        let mut masks = vec![0; 256];
+        for (i, rule) in rules.iter().enumerate() {
+            for &b in &rule.members {
+                masks[usize::from(b)] |= 1 << i;
+            }
+        }
+        let next = (0..num_states)
+            .map(|state| {
+                let counts = decode(state);
+                (0..1 << rules.len())
+                    .map(|mask: usize| {
+                        let counts: Vec<_> = counts
+                            .iter()
+                            .zip(&caps)
+                            .enumerate()
+                            .map(|(i, (&count, &cap))| match mask >> i & 1 {
+                                1 => cap.min(count + 1),
+                                _ => count,
+                            })
+                            .collect();
+                        encode(&counts)
+                    })
+                    .collect()
+            })
+            .collect();
+        let accept = (0..num_states)
+            .map(|state| {
+                decode(state).iter().zip(rules).all(|(&count, rule)| {
+                    count >= rule.min && rule.max.is_none_or(|max| count <= max)
+                })
+            })
+            .collect();
+        Self {
+            masks,
+            next,
+            accept,
+        }
+    }
+    /// Returns the number of states
+    pub(crate) fn len(&self) -> usize {
+        self.next.len()
+    }
+    /// Returns the masks of the bytes in a value that count towards a rule,
+    /// sorted, since the order that they're counted in doesn't matter
+    fn effect(&self, value: &[u8]) -> Vec<usize> {
+        let mut masks: Vec<_> = value
+            .iter()
+            .map(|&b| usize::from(self.masks[usize::from(b)]))
+            .filter(|&mask| mask != 0)
+            .collect();
+        masks.sort_unstable();
+        masks
+    }
+    /// Returns the state after counting the masks of a value `times` times
+    fn apply(&self, state: usize, effect: &[usize], times: usize) -> usize {
+        (0..times).fold(state, |state, _| {
+            effect
+                .iter()
+                .fold(state, |state, &mask| self.next[state][mask])
+        })
+    }
+}
+
+/// How a run follows a policy
+#[derive(Clone, PartialEq, Debug)]
+pub struct Compliance {
+    /// The state after counting the run's inits
+    pub start: usize,
+    /// For every change that can write a byte that counts towards the
+    /// policy, the states after it that can still end up following every rule
+    pub feasible: Vec<Option<Vec<bool>>>,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Init {
+    pub val: u8,
+    pub buf_idx: usize,
+}
+
+impl Init {
+    fn new(buf_idx: usize, val: u8) -> Self {
+        Self { val, buf_idx }
+    }
+}
+
+/// Where a change gets its values from
+#[derive(Clone, PartialEq, Debug)]
+pub enum Values {
+    /// The members of the class with this id
+    Class(usize),
+    /// The members of the words with this id, which have different widths;
+    /// the change's `width` is the narrowest one's
+    Words(usize),
+    /// The integers counting up from `start`, rendered like `Token::Number`
+    Number {
+        start: u64,
+        digits: &'static [u8],
+        pad: u8,
+    },
+    /// The integers counting up from `start`, written like `Token::Int`
+    Int { start: u64, big_endian: bool },
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Change {
+    pub buf_idx: usize,
+    pub values: Values,
+    pub width: usize,
+    pub lower: usize,
+    pub upper: usize,
+    /// Other buf indexes that backreferences copy this change's value to
+    pub mirrors: Vec<usize>,
+    /// The buf indexes of earlier changes that this change's value has to
+    /// differ from, which draw from classes or words that can share members
+    pub distinct: Vec<usize>,
+    /// The index of the pool in the run that this change draws members from
+    /// without replacement, if any
+    pub pool: Option<usize>,
+}
+
+impl Change {
+    fn new(idx: usize, class_id: usize, upper: usize, width: usize) -> Self {
+        Self {
+            buf_idx: idx,
+            values: Values::Class(class_id),
+            width,
+            lower: 0,
+            upper,
+            mirrors: vec![],
+            distinct: vec![],
+            pool: None,
+        }
+    }
+    /// Writes the `m`th value of the change to the start of `out` and returns
+    /// its width, where `members` holds the members of the change's class or
+    /// words (if it draws from either) and `offsets` where each word starts
+    pub fn write(&self, m: usize, members: &[u8], offsets: &[usize], out: &mut [u8]) -> usize {
+        let width = self.width;
+        match self.values {
+            Values::Class(_) => out[..width].copy_from_slice(&members[m * width..(m + 1) * width]),
+            Values::Words(_) => {
+                let word = &members[offsets[m]..offsets[m + 1]];
+                out[..word.len()].copy_from_slice(word);
+                return word.len();
+            }
+            Values::Number { start, digits, pad } => {
+                let radix = digits.len() as u64;
+                let mut n = start + m as u64;
+                for i in (0..width).rev() {
+                    out[i] = if n == 0 && i + 1 < width {
+                        pad
+                    } else {
+                        digits[(n % radix) as usize]
+                    };
+                    n /= radix;
+                }
+            }
+            // the integer is truncated to `width` bytes
+            Values::Int { start, big_endian } => {
+                let n = start + m as u64;
+                if big_endian {
+                    out[..width].copy_from_slice(&n.to_be_bytes()[8 - width..]);
+                } else {
+                    out[..width].copy_from_slice(&n.to_le_bytes()[..width]);
+                }
+            }
+        }
+        width
+    }
+    /// Returns whether any byte that the change can write counts towards a
+    /// rule of the policy
+    fn counts_towards(
+        &self,
+        policy: &Policy,
+        classes: &HashMap<usize, &[u8]>,
+        words: &HashMap<usize, (&[u8], &[usize])>,
+    ) -> bool {
+        let counts = |b: &u8| policy.masks[usize::from(*b)] != 0;
+        match self.values {
+            Values::Class(id) => classes[&id].iter().any(counts),
+            Values::Words(id) => words[&id].0.iter().any(counts),
+            Values::Number { digits, pad, .. } => digits.iter().chain(Some(&pad)).any(counts),
+            Values::Int { .. } => policy.masks.iter().any(|&mask| mask != 0),
+        }
+    }
+    /// Returns every value of the change, or None if it's a number or integer
+    /// range with more than `MAX_COUNTED` values
+    fn values(
+        &self,
+        classes: &HashMap<usize, &[u8]>,
+        words: &HashMap<usize, (&[u8], &[usize])>,
+    ) -> Option<Vec<Vec<u8>>> {
+        let (members, offsets) = match self.values {
+            Values::Class(id) => (classes[&id], &[][..]),
+            Values::Words(id) => words[&id],
+            _ if self.upper - self.lower > MAX_COUNTED => return None,
+            _ => (&[][..], &[][..]),
+        };
+        Some(
+            (self.lower..self.upper)
+                .map(|m| {
+                    let width = match self.values {
+                        Values::Words(_) => offsets[m + 1] - offsets[m],
+                        _ => self.width,
+                    };
+                    let mut value = vec![0; width];
+                    self.write(m, members, offsets, &mut value);
+                    value
+                })
+                .collect(),
+        )
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Run {
+    pub inits: Vec<Init>,
+    pub changes: Vec<Change>,
+    pub len: usize,
+    /// How many times each member of a class can be drawn, for every pool
+    /// that a change draws from
+    pub pools: Vec<Vec<usize>>,
+    /// How the run follows the policy, if there is one
+    pub compliance: Option<Compliance>,
+    /// Where the run writes words, in order; everything after each one
+    /// moves by however much wider its value is than the change's width
+    pub shifts: Vec<Shift>,
+    /// The lengths that candidates have to have, if only some of the run's
+    /// candidates have them
+    pub lengths: Option<RangeInclusive<usize>>,
+    /// For a run without changes that's split across threads, the members
+    /// that the first member added by an open-ended range takes in this
+    /// thread; only the thread whose share starts at 0 tries the run without
+    /// any members added
+    pub first_grown: Option<Range<usize>>,
+}
+
+/// Somewhere that a change draws words into, either the change itself or a
+/// backreference to it
+#[derive(Clone, PartialEq, Debug)]
+pub struct Shift {
+    /// Where it would be if every word before it were as narrow as it gets
+    pub buf_idx: usize,
+    /// The index of the change
+    pub change: usize,
+    /// The most that everything after it can move by
+    pub most: usize,
+}
+
+/// Something that a run writes once the changes that it depends on have
+/// taken their values
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Write {
+    /// Works out where everything after the shift with this index moves to
+    Shift(usize),
+    /// Writes the init with this index
+    Init(usize),
+    /// Copies the value of the change with index `change` to `buf_idx`
+    Mirror { change: usize, buf_idx: usize },
+}
+
+/// What writes each byte of a run's buffer
+#[derive(Clone, Copy)]
+enum Source {
+    Init(u8),
+    /// The first byte of the change with this index
+    Change(usize),
+    /// Any later byte of a change wider than one byte
+    Covered,
+}
+
+impl Run {
+    fn new(tokens: &[Token]) -> Self {
+        let mut tokens = tokens.iter().peekable();
+        let mut run = Run {
+            inits: vec![],
+            changes: vec![],
+            len: 0,
+            pools: vec![],
+            compliance: None,
+            shifts: vec![],
+            lengths: None,
+            first_grown: None,
+        };
+        let mut sources = vec![];
+        let mut starts = HashMap::new();
+        let mut captures = HashMap::new();
+        // the indexes of the changes in each distinct span that's still open
+        let mut spans: Vec<Vec<usize>> = vec![];
+        while let Some(t) = tokens.next() {
+            let n = match tokens.peek() {
+                Some(&&Token::Repeat(n)) => {
+                    tokens.next();
+                    n
+                }
+                _ => 1,
+            };
+            for _ in 0..n {
+                match *t {
+                    Token::Char(c) => {
+                        run.inits.push(Init::new(sources.len(), c));
+                        sources.push(Source::Init(c));
+                    }
+                    Token::Class {
+                        id,
+                        len: size,
+                        width,
+                    } => {
+                        let change = Change::new(sources.len(), id, size, width);
+                        run.push_change(&mut sources, &mut spans, change);
+                    }
+                    Token::Words {
+                        id,
+                        len: size,
+                        width,
+                        widest,
+                    } => {
+                        run.shifts.push(Shift {
+                            buf_idx: sources.len(),
+                            change: run.changes.len(),
+                            most: widest - width,
+                        });
+                        let change = Change {
+                            values: Values::Words(id),
+                            ..Change::new(sources.len(), 0, size, width)
+                        };
+                        run.push_change(&mut sources, &mut spans, change);
+                    }
+                    Token::Anagram {
+                        id,
+                        width,
+                        ref counts,
+                    } => {
+                        for _ in 0..counts.iter().sum() {
+                            let change = Change {
+                                pool: Some(run.pools.len()),
+                                ..Change::new(sources.len(), id, counts.len(), width)
+                            };
+                            run.push_change(&mut sources, &mut spans, change);
+                        }
+                        run.pools.push(counts.clone());
+                    }
+                    Token::Number {
+                        start,
+                        len: size,
+                        width,
+                        digits,
+                        pad,
+                    } => {
+                        let change = Change {
+                            values: Values::Number { start, digits, pad },
+                            ..Change::new(sources.len(), 0, size, width)
+                        };
+                        run.push_change(&mut sources, &mut spans, change);
+                    }
+                    Token::Int {
+                        start,
+                        len: size,
+                        width,
+                        big_endian,
+                    } => {
+                        let change = Change {
+                            values: Values::Int { start, big_endian },
+                            ..Change::new(sources.len(), 0, size, width)
+                        };
+                        run.push_change(&mut sources, &mut spans, change);
+                    }
+                    Token::Open(group) => {
+                        starts.insert(group, sources.len());
+                    }
+                    Token::Close(group) => {
+                        captures.insert(group, starts[&group]..sources.len());
+                    }
+                    // a group that didn't take part in this run captures nothing
+                    Token::Backref(group) => {
+                        for i in captures.get(&group).cloned().unwrap_or_default() {
+                            let source = sources[i];
+                            match source {
+                                Source::Init(c) => run.inits.push(Init::new(sources.len(), c)),
+                                Source::Change(j) => {
+                                    if let Some(shift) =
+                                        run.shifts.iter().find(|shift| shift.change == j)
+                                    {
+                                        run.shifts.push(Shift {
+                                            buf_idx: sources.len(),
+                                            ..shift.clone()
+                                        });
+                                    }
+                                    run.changes[j].mirrors.push(sources.len());
+                                }
+                                Source::Covered => {}
+                            }
+                            sources.push(source);
+                        }
+                    }
+                    Token::BeginDistinct => spans.push(vec![]),
+                    Token::EndDistinct => {
+                        spans.pop();
+                    }
+                    _ => unreachable!(),
+                }
+            }
+        }
+        run.len = sources.len();
+        run
+    }
+    /// Returns the length of the run's longest candidates
+    pub fn max_len(&self) -> usize {
+        self.len + self.shifts.iter().map(|shift| shift.most).sum::<usize>()
+    }
+    /// Returns the index of the last shift before `buf_idx`, which moves
+    /// whatever is there
+    pub fn shift_before(&self, buf_idx: usize) -> Option<usize> {
+        self.shifts
+            .iter()
+            .rposition(|shift| shift.buf_idx < buf_idx)
+    }
+    /// Returns how many changes have to take their values before whatever is
+    /// at `buf_idx` can be written, since it moves with the shifts before it
+    fn depth(&self, buf_idx: usize) -> usize {
+        self.shifts
+            .iter()
+            .take_while(|shift| shift.buf_idx < buf_idx)
+            .map(|shift| shift.change + 1)
+            .max()
+            .unwrap_or(0)
+    }
+    /// Returns what the run writes besides the changes' own values, grouped
+    /// by how many changes have to take their values first; the shifts come
+    /// first in each group, since everything else is written after them
+    pub fn writes(&self) -> Vec<Vec<Write>> {
+        let mut writes = vec![vec![]; self.changes.len() + 1];
+        for (k, shift) in self.shifts.iter().enumerate() {
+            writes[self.depth(shift.buf_idx + 1)].push(Write::Shift(k));
+        }
+        for (i, init) in self.inits.iter().enumerate() {
+            writes[self.depth(init.buf_idx)].push(Write::Init(i));
+        }
+        for (j, change) in self.changes.iter().enumerate() {
+            for &buf_idx in &change.mirrors {
+                writes[self.depth(buf_idx).max(j + 1)].push(Write::Mirror { change: j, buf_idx });
+            }
+        }
+        writes
+    }
+    /// Works out which states of the policy each change can lead to without
+    /// breaking a rule later on, where `classes` and `words` map ids to
+    /// their members; returns false if no candidate in the run follows the
+    /// policy, or an error if a range has too many values to count
+    fn comply(
+        &mut self,
+        policy: &Policy,
+        classes: &HashMap<usize, &[u8]>,
+        words: &HashMap<usize, (&[u8], &[usize])>,
+    ) -> Result<bool, ParseErr> {
+        let start = self.inits.iter().fold(0, |state, init| {
+            policy.apply(state, &policy.effect(&[init.val]), 1)
+        });
+        // the distinct effects of the values that each counted change can
+        // write, and how many times it writes them (once more for each mirror)
+        let steps = self
+            .changes
+            .iter()
+            .map(|change| {
+                if !change.counts_towards(policy, classes, words) {
+                    return Ok(None);
+                }
+                let values = change.values(classes, words).ok_or_else(|| {
+                    ParseErr::new("policies can't count ranges with more than 65536 values", 0)
+                })?;
+                let mut effects: Vec<_> = values.iter().map(|v| policy.effect(v)).collect();
+                effects.sort_unstable();
+                effects.dedup();
+                Ok(Some((effects, 1 + change.mirrors.len())))
+            })
+            .collect::<Result<Vec<_>, ParseErr>>()?;
+        // feasible[j] holds the states before change j that can still end up
+        // following every rule, so the last one is just the accepted states
+        let mut feasible = vec![policy.accept.clone()];
+        for step in steps.iter().rev() {
+            let after = feasible.last().expect("feasible is nonempty");
+            let before = match step {
+                Some((effects, times)) => (0..policy.len())
+                    .map(|state| {
+                        effects
+                            .iter()
+                            .any(|effect| after[policy.apply(state, effect, *times)])
+                    })
+                    .collect(),
+                None => after.clone(),
+            };
+            feasible.push(before);
+        }
+        feasible.reverse();
+        if !feasible[0][start] {
+            return Ok(false);
+        }
+        self.compliance = Some(Compliance {
+            start,
+            feasible: steps
+                .iter()
+                .zip(feasible.into_iter().skip(1))
+                .map(|(step, after)| step.as_ref().map(|_| after))
+                .collect(),
+        });
+        Ok(true)
+    }
+    /// Returns the number of candidates of each length in the run that follow
+    /// the policy (if there is one), saturating at u128::MAX, where `classes`
+    /// maps class ids to their members and `words` maps the ids of words to
+    /// their members and offsets
+    fn count(
+        &self,
+        policy: Option<&Policy>,
+        classes: &HashMap<usize, &[u8]>,
+        words: &HashMap<usize, (&[u8], &[usize])>,
+    ) -> HashMap<usize, u128> {
+        // the masks of the bytes in a value that count towards the policy
+        let mask = |value: &[u8]| policy.map(|policy| policy.effect(value));
+        let domain = |change: &Change| -> Vec<&[u8]> {
+            match change.values {
+                Values::Class(id) => classes[&id]
+                    .chunks_exact(change.width)
+                    .skip(change.lower)
+                    .take(change.upper - change.lower)
+                    .collect(),
+                Values::Words(id) => {
+                    let (members, offsets) = words[&id];
+                    offsets[change.lower..=change.upper]
+                        .windows(2)
+                        .map(|w| &members[w[0]..w[1]])
+                        .collect()
+                }
+                _ => vec![],
+            }
+        };
+        // every change in a distinct span has to differ from the earlier ones,
+        // so the changes are grouped by the first change that they refer to
+        let mut groups: Vec<Option<usize>> = vec![None; self.changes.len()];
+        let mut num_groups = 0;
+        for (j, change) in self.changes.iter().enumerate() {
+            if let Some(&first) = change.distinct.first() {
+                let k = self
+                    .changes
+                    .iter()
+                    .position(|other| other.buf_idx == first)
+                    .expect("changes only differ from earlier changes");
+                let group = *groups[k].get_or_insert_with(|| {
+                    num_groups += 1;
+                    num_groups - 1
+                });
+                groups[j] = Some(group);
+            }
+        }
+        // a state holds the policy's state, then how many values are taken
+        // from each atom of each group, then how many times each member of
+        // each pool is drawn, then how much longer than `len` the words make
+        // the candidate; `caps` holds the most that each slot can be
+        let mut caps = vec![0];
+        // the values in a group are split into atoms, which are the values
+        // that every change in the group treats the same: each change either
+        // can or can't take all of them, they count towards the same rules,
+        // and they have the same width. Values that a pool draws can't share
+        // an atom, since the pool tells them apart
+        let mut slots: Vec<HashMap<&[u8], usize>> = vec![HashMap::new(); num_groups];
+        for (g, slots) in slots.iter_mut().enumerate() {
+            let changes: Vec<_> = (0..self.changes.len())
+                .filter(|&j| groups[j] == Some(g))
+                .map(|j| &self.changes[j])
+                .collect();
+            let pooled = changes.iter().any(|change| change.pool.is_some());
+            let domains: Vec<_> = changes.iter().map(|&change| domain(change)).collect();
+            let mut atoms = HashMap::new();
+            for &value in domains.iter().flatten() {
+                if slots.contains_key(value) {
+                    continue;
+                }
+                let key = (
+                    domains
+                        .iter()
+                        .map(|d| d.contains(&value))
+                        .collect::<Vec<_>>(),
+                    mask(value),
+                    value.len(),
+                    if pooled { Some(value) } else { None },
+                );
+                let slot = *atoms.entry(key).or_insert_with(|| {
+                    caps.push(0);
+                    caps.len() - 1
+                });
+                caps[slot] += 1;
+                slots.insert(value, slot);
+            }
+        }
+        let pool_starts: Vec<_> = self
+            .pools
+            .iter()
+            .map(|pool| {
+                caps.extend(pool);
+                caps.len() - pool.len()
+            })
+            .collect();
+        // the ways that each change can pick its value: `count` values that
+        // count towards the rules in `mask` and make the candidate `extra`
+        // bytes longer, and take one more from the atom or pool in those
+        // slots
+        struct Choice {
+            count: u128,
+            mask: Option<Vec<usize>>,
+            extra: usize,
+            atom: Option<usize>,
+            pool: Option<usize>,
+        }
+        let choices = self.changes.iter().enumerate().map(|(j, change)| {
+            // numbers and integers are only listed if they count towards the
+            // policy, which `comply` checks they can be
+            let listed = match (policy, &change.values) {
+                (_, Values::Class(_) | Values::Words(_)) | (None, _) => None,
+                (Some(policy), _) if change.counts_towards(policy, classes, words) => {
+                    change.values(classes, words)
+                }
+                _ => None,
+            };
+            let values = match &listed {
+                Some(listed) => listed.iter().map(Vec::as_slice).collect(),
+                None => domain(change),
+            };
+            // backreferences copy the value, so they make it longer too
+            let extra = |value: &[u8]| (value.len() - change.width) * (1 + change.mirrors.len());
+            match (change.pool, groups[j]) {
+                _ if values.is_empty() => vec![Choice {
+                    count: (change.upper - change.lower) as u128,
+                    mask: None,
+                    extra: 0,
+                    atom: None,
+                    pool: None,
+                }],
+                (Some(k), group) => values
+                    .iter()
+                    .enumerate()
+                    .map(|(m, &value)| Choice {
+                        count: 1,
+                        mask: mask(value),
+                        extra: extra(value),
+                        atom: group.map(|g| slots[g][value]),
+                        pool: Some(pool_starts[k] + change.lower + m),
+                    })
+                    .collect(),
+                (None, Some(g)) => {
+                    let mut atoms: Vec<_> = values
+                        .iter()
+                        .map(|&value| (slots[g][value], mask(value), extra(value)))
+                        .collect();
+                    atoms.sort_unstable();
+                    atoms.dedup();
+                    atoms
+                        .into_iter()
+                        .map(|(slot, mask, extra)| Choice {
+                            count: 1,
+                            mask,
+                            extra,
+                            atom: Some(slot),
+                            pool: None,
+                        })
+                        .collect()
+                }
+                (None, None) => {
+                    let mut masks: Vec<(Option<Vec<usize>>, usize, u128)> = vec![];
+                    for &value in &values {
+                        let (mask, extra) = (mask(value), extra(value));
+                        match masks.iter_mut().find(|(m, e, _)| *m == mask && *e == extra) {
+                            Some((_, _, count)) => *count += 1,
+                            None => masks.push((mask, extra, 1)),
+                        }
+                    }
+                    masks
+                        .into_iter()
+                        .map(|(mask, extra, count)| Choice {
+                            count,
+                            mask,
+                            extra,
+                            atom: None,
+                            pool: None,
+                        })
+                        .collect()
+                }
+            }
+        });
+        let start = policy.map_or(0, |policy| {
+            self.inits.iter().fold(0, |state, init| {
+                policy.apply(state, &policy.effect(&[init.val]), 1)
+            })
+        });
+        let mut initial = vec![0; caps.len() + 1];
+        initial[0] = start;
+        let mut states = HashMap::new();
+        states.insert(initial, 1u128);
+        for (change, choices) in self.changes.iter().zip(choices) {
+            let mut next = HashMap::new();
+            for (state, ways) in states {
+                for choice in &choices {
+                    let mut ways = ways.saturating_mul(choice.count);
+                    let mut after = state.clone();
+                    if let Some(slot) = choice.atom {
+                        ways = ways.saturating_mul((caps[slot] - state[slot]) as u128);
+                        after[slot] += 1;
+                    }
+                    if let Some(slot) = choice.pool {
+                        if state[slot] == caps[slot] {
+                            continue;
+                        }
+                        after[slot] += 1;
+                    }
+                    if let (Some(policy), Some(mask)) = (policy, &choice.mask) {
+                        // backreferences count the value again
+                        after[0] = policy.apply(after[0], mask, 1 + change.mirrors.len());
+                    }
+                    *after.last_mut().expect("states hold the extra length") += choice.extra;
+                    if ways > 0 {
+                        let total = next.entry(after).or_insert(0u128);
+                        *total = total.saturating_add(ways);
+                    }
+                }
+            }
+            states = next;
+        }
+        let mut counts = HashMap::new();
+        for (state, ways) in states {
+            if policy.is_none_or(|policy| policy.accept[state[0]]) {
+                let len = self.len + state.last().expect("states hold the extra length");
+                let total = counts.entry(len).or_insert(0u128);
+                *total = total.saturating_add(ways);
+            }
+        }
+        counts
+    }
+    /// Adds a change; changes that draw from a class or words have to differ
+    /// from the earlier ones that can take the same values in every distinct
+    /// span that they're in
+    fn push_change(
+        &mut self,
+        sources: &mut Vec<Source>,
+        spans: &mut [Vec<usize>],
+        mut change: Change,
+    ) {
+        if let Values::Class(_) | Values::Words(_) = change.values {
+            for span in spans.iter_mut() {
+                for &j in span.iter() {
+                    let other = &self.changes[j];
+                    let comparable = match (&other.values, &change.values) {
+                        (Values::Class(_), Values::Class(_)) => other.width == change.width,
+                        _ => true,
+                    };
+                    if comparable && !change.distinct.contains(&other.buf_idx) {
+                        change.distinct.push(other.buf_idx);
+                    }
+                }
+                span.push(self.changes.len());
+            }
+        }
+        sources.push(Source::Change(self.changes.len()));
+        sources.extend(repeat_n(Source::Covered, change.width - 1));
+        self.changes.push(change);
+    }
+}
+
+struct Permutor {
+    maxs: Vec<usize>,
+    buf: Vec<Cell<usize>>, // use interior mutability to avoid handing out mutable references
+}
+
+impl Permutor {
+    fn new(maxs: Vec<usize>) -> Self {
+        Self {
+            buf: vec![Cell::new(0); maxs.len()],
+            maxs,
+        }
+    }
+    fn get_iter(&self, i: usize) -> impl Iterator<Item = usize> + '_ {
+        let mut rem = i;
+        // we reverse to start incrementing from the back
+        for (slot, max) in self.buf.iter().zip(self.maxs.iter()).rev() {
+            let modulo = rem % max;
+            slot.set(modulo);
+            rem = (rem - modulo) / max;
+        }
+
+        self.buf.iter().map(|c| c.get())
+    }
+
+    fn total(&self) -> usize {
+        self.maxs
+            .iter()
+            .try_fold(1usize, |total, &max| total.checked_mul(max))
+            .expect("the number of runs is checked before expanding")
+    }
+
+    fn permutations(&self) -> impl Iterator<Item = impl Iterator<Item = usize> + '_> {
+        (0..self.total()).map(move |i| self.get_iter(i))
+    }
+}
+
+/// The members of an open-ended range at the end of the pattern, which are
+/// added to the end of every run at runtime, one more each time the runs
+/// are exhausted
+#[derive(Clone, PartialEq, Debug)]
+pub struct Growth {
+    pub class_id: usize,
+    pub width: usize,
+    /// The number of members in the class
+    pub len: usize,
+    /// The lengths that candidates are allowed to have, which end at
+    /// `Limits::max_grown_len` unless the length limit has an upper bound
+    pub lengths: RangeInclusive<usize>,
+}
+
+impl Growth {
+    /// Returns the number of candidates that a run grows into, saturating at
+    /// u128::MAX, where `count` is the number of the run's candidates that
+    /// have length `len`
+    fn count(&self, count: u128, len: usize, run: &Run) -> u128 {
+        if count == 0 || len > *self.lengths.end() {
+            return 0;
+        }
+        let (min, max) = (*self.lengths.start(), *self.lengths.end());
+        // the number of members that can be added, and the fewest that have
+        // to be
+        let most = (max - len) / self.width;
+        let fewest = min.saturating_sub(len).div_ceil(self.width);
+        if fewest > most {
+            return 0;
+        }
+        if self.len == 1 {
+            return count.saturating_mul((most - fewest + 1) as u128);
+        }
+        // the run's thread might only take some of the first added member
+        let (ungrown, taken) = match &run.first_grown {
+            Some(first) => (first.start == 0, first.len()),
+            None => (true, self.len),
+        };
+        let mut total = if fewest == 0 && ungrown { count } else { 0 };
+        let fewest = fewest.max(1);
+        let mut grown = count
+            .saturating_mul(taken as u128)
+            .saturating_mul((self.len as u128).saturating_pow((fewest - 1).min(128) as u32));
+        for _ in fewest..=most {
+            total = total.saturating_add(grown);
+            if total == u128::MAX {
+                break;
+            }
+            grown = grown.saturating_mul(self.len as u128);
+        }
+        total
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Final {
+    pub tasks: Vec<Vec<Run>>,
+    pub max_size: usize,
+    pub statics: Dict,
+    pub words: Words,
+    pub files: Vec<PathBuf>,
+    pub policy: Option<Policy>,
+    pub warnings: Vec<ParseErr>,
+    pub growth: Option<Growth>,
+    /// The number of candidates in each task, saturating at u128::MAX
+    pub keyspace: Vec<u128>,
+    /// How long enumerating can take before it stops early
+    pub timeout: Option<Duration>,
+}
+
+impl Final {
+    pub fn new(
+        src: &str,
+        opts: &Options,
+        limits: &Limits,
+        num_threads: usize,
+        part: Partition,
+    ) -> Result<Self, ParseErr> {
+        let (ctx, tokens) = Lexer::tokenize(src, opts)?;
+        Self::from_tokens(ctx, &tokens, limits, num_threads, part)
+    }
+    /// Builds the runs for the tokens, which may be empty if none of them
+    /// fit the limits
+    pub fn from_tokens(
+        mut ctx: Context,
+        tokens: &[Token],
+        limits: &Limits,
+        num_threads: usize,
+        part: Partition,
+    ) -> Result<Self, ParseErr> {
+        if let Some(i) = Self::too_many_runs(tokens) {
+            return Err(ParseErr::new(
+                "pattern should expand to at most 65536 runs",
+                ctx.offsets.get(i).copied().unwrap_or(0),
+            ));
+        }
+        // the runs are built with the fewest repeats that an open-ended
+        // range allows, and the rest are added at runtime
+        let (tokens, growth) = match tokens {
+            [rest @ .., last, Token::AtLeast(n)] => {
+                let class = match *last {
+                    Token::Char(b) => intern(&mut ctx.dict, vec![b].into(), 1),
+                    _ => last.clone(),
+                };
+                let growth = match class {
+                    Token::Class { id, width, len } if len > 0 => Some(Growth {
+                        class_id: id,
+                        width,
+                        len,
+                        lengths: match &limits.length {
+                            Some(l) if *l.end() < usize::MAX => l.clone(),
+                            Some(l) => *l.start()..=limits.max_grown_len,
+                            None => 0..=limits.max_grown_len,
+                        },
+                    }),
+                    Token::Class { .. } => None,
+                    _ => unreachable!("the lexer checks what open-ended ranges follow"),
+                };
+                let mut tokens = rest.to_vec();
+                match *n {
+                    0 => {}
+                    1 => tokens.push(last.clone()),
+                    n => tokens.extend(vec![last.clone(), Token::Repeat(n)]),
+                }
+                (Cow::Owned(tokens), growth)
+            }
+            _ => (Cow::Borrowed(tokens), None),
+        };
+        let mut runs: Vec<_> = Self::expand(&tokens)
+            .iter()
+            .map(|t| Run::new(t))
+            .filter_map(|mut run| {
+                if let Some(l) = &limits.length {
+                    // longer candidates come from growing the run
+                    let fits = match growth {
+                        Some(_) => run.len <= *l.end(),
+                        None => run.len <= *l.end() && run.max_len() >= *l.start(),
+                    };
+                    if !fits {
+                        return None;
+                    }
+                    // words make some candidates longer than others, so if
+                    // only some of them fit, they're checked one at a time
+                    let all_fit =
+                        growth.is_none() && l.contains(&run.len) && l.contains(&run.max_len());
+                    if !run.shifts.is_empty() && !all_fit {
+                        run.lengths = Some(l.clone());
+                    }
+                }
+                Some(run)
+            })
+            .collect();
+        let (classes, words) = (by_id(&ctx.dict), words_by_id(&ctx.words));
+        let policy = if limits.policy.is_empty() {
+            None
+        } else {
+            let policy = Policy::new(&limits.policy);
+            let mut compliant = Vec::with_capacity(runs.len());
+            for mut run in runs {
+                if run.comply(&policy, &classes, &words)? {
+                    compliant.push(run);
+                }
+            }
+            runs = compliant;
+            Some(policy)
+        };
+        if limits.shortest_first {
+            // this is a stable sort, so runs of the same length stay in order
+            runs.sort_by_key(|run| run.len);
+        }
+
+        let max_size = runs.iter().map(Run::max_len).max().unwrap_or(0);
+
+        let mut result = Self {
+            max_size,
+            tasks: vec![runs],
+            statics: ctx.dict,
+            words: ctx.words,
+            files: ctx.files,
+            policy,
+            warnings: ctx.warnings,
+            growth,
+            keyspace: vec![],
+            timeout: limits.timeout,
+        };
+        match part {
+            Partition::Naive => result.partition(num_threads),
+            Partition::None => result.count_keyspace(),
+        }
+        Ok(result)
+    }
+    /// Splits the runs into `n` tasks, one for each thread, and counts the
+    /// candidates in each; the runs should all be in one task beforehand,
+    /// like they are when they're built with `Partition::None`
+    pub fn partition(&mut self, n: usize) {
+        let runs = std::mem::take(&mut self.tasks[0]);
+        self.tasks = self.weak_partition(runs, n);
+        self.count_keyspace();
+    }
+    /// Counts the candidates in each task
+    fn count_keyspace(&mut self) {
+        let (classes, words) = (by_id(&self.statics), words_by_id(&self.words));
+        self.keyspace = self
+            .tasks
+            .iter()
+            .map(|task| {
+                task.iter()
+                    .map(|run| self.total(run, &classes, &words, true))
+                    .fold(0, u128::saturating_add)
+            })
+            .collect();
+    }
+    /// Returns the number of candidates in a run that follow the policy and
+    /// fit the limits, saturating at u128::MAX, where `classes` and `words`
+    /// map ids to their members; if `grown` is false, the run isn't grown by
+    /// an open-ended range and its candidates aren't checked against the
+    /// limits that the growth is
+    fn total(
+        &self,
+        run: &Run,
+        classes: &HashMap<usize, &[u8]>,
+        words: &HashMap<usize, (&[u8], &[usize])>,
+        grown: bool,
+    ) -> u128 {
+        run.count(self.policy.as_ref(), classes, words)
+            .into_iter()
+            .map(|(len, count)| match &self.growth {
+                Some(g) if grown => g.count(count, len, run),
+                Some(_) => count,
+                None if run.lengths.as_ref().is_some_and(|l| !l.contains(&len)) => 0,
+                None => count,
+            })
+            .fold(0, u128::saturating_add)
+    }
+    /// Returns how many runs the tokens expand to, saturating at u128::MAX
+    fn count_runs(tokens: &[Token]) -> u128 {
+        Self::choices(tokens)
+            .into_iter()
+            .fold(1, |total, (_, n)| total.saturating_mul(n))
+    }
+    /// Returns the index of the token where the tokens start to expand to
+    /// more than `MAX_RUNS` runs, if they do
+    fn too_many_runs(tokens: &[Token]) -> Option<usize> {
+        let mut total = 1u128;
+        Self::choices(tokens).into_iter().find_map(|(i, n)| {
+            total = total.saturating_mul(n);
+            (total > MAX_RUNS).then_some(i)
+        })
+    }
+    /// Returns how many runs each token expands to, saturating at u128::MAX,
+    /// along with the index of the range or repeat that follows it, or of
+    /// the token itself if there isn't one
+    fn choices(tokens: &[Token]) -> Vec<(usize, u128)> {
+        let mut choices = vec![];
+        let mut i = 0;
+        while i < tokens.len() {
+            let t = &tokens[i];
+            let counts = match tokens.get(i + 1) {
+                Some(&Token::Range { lower, upper }) => lower..=upper,
+                Some(&Token::Repeat(n)) => n..=n,
+                _ => 1..=1,
+            };
+            if counts != (1..=1) {
+                i += 1;
+            }
+            let n = match t {
+                Token::Group(alts) => {
+                    let alts = alts
+                        .iter()
+                        .map(|alt| Self::count_runs(alt))
+                        .fold(0, u128::saturating_add);
+                    counts
+                        .map(|n| alts.saturating_pow(n as u32))
+                        .fold(0, u128::saturating_add)
+                }
+                _ => counts.count() as u128,
+            };
+            choices.push((i, n));
+            i += 1;
+        }
+        choices
+    }
+    /// Flattens groups and ranges into every sequence of characters, classes,
+    /// and repeats that the tokens can produce; the earliest choice in the
+    /// pattern varies the slowest, and alternatives are taken in the order
+    /// they were written
+    fn expand(tokens: &[Token]) -> Vec<Vec<Token>> {
+        let mut choices: Vec<Vec<Vec<Token>>> = vec![];
+        let mut tokens = tokens.iter().peekable();
+        while let Some(t) = tokens.next() {
+            let counts = match tokens.peek() {
+                Some(&&Token::Range { lower, upper }) => lower..=upper,
+                Some(&&Token::Repeat(n)) => n..=n,
+                _ => 1..=1,
+            };
+            if counts != (1..=1) {
+                tokens.next();
+            }
+            choices.push(match t {
+                Token::Group(alts) => {
+                    let alts: Vec<_> = alts.iter().flat_map(|alt| Self::expand(alt)).collect();
+                    counts
+                        .flat_map(|n| Self::product(&vec![alts.clone(); n]))
+                        .collect()
+                }
+                _ => counts.map(|n| vec![t.clone(), Token::Repeat(n)]).collect(),
+            });
+        }
+        Self::product(&choices)
+    }
+    /// Concatenates every combination of one sequence from each set of
+    /// choices, with the first set varying the slowest
+    fn product(choices: &[Vec<Vec<Token>>]) -> Vec<Vec<Token>> {
+        Permutor::new(choices.iter().map(Vec::len).collect())
+            .permutations()
+            .map(|p| {
+                p.zip(choices.iter())
+                    .flat_map(|(i, choice)| choice[i].iter().cloned())
+                    .collect()
+            })
+            .collect()
+    }
+    /// Splits `t` values into `n` consecutive shares of about the same size
+    fn shares(t: usize, n: usize) -> impl Iterator<Item = Range<usize>> {
+        repeat_n(t / n + 1, t % n)
+            .chain(repeat_n(t / n, n - t % n))
+            .scan(0, |acc, cur| {
+                let old = *acc;
+                *acc += cur;
+                Some(old..*acc)
+            })
+    }
+    /// Splits the runs into `n` tasks by dividing up the values of each run's
+    /// first change, so that every task gets about the same number of the
+    /// run's candidates that follow the policy and fit the limits; a run
+    /// without changes is split by the first member that an open-ended range
+    /// adds to it, if there is one
+    pub(crate) fn weak_partition(&self, runs: Vec<Run>, n: usize) -> Vec<Vec<Run>> {
+        let (classes, words) = (by_id(&self.statics), words_by_id(&self.words));
+        let mut tasks = vec![Vec::with_capacity(runs.len()); n];
+        for run in runs {
+            let (lower, upper) = match (run.changes.first(), &self.growth) {
+                (Some(first), _) => (first.lower, first.upper),
+                // a run without changes is split by the first member that an
+                // open-ended range adds to it
+                (None, Some(growth)) => {
+                    for (i, share) in Self::shares(growth.len, n).enumerate() {
+                        if !share.is_empty() {
+                            tasks[i].push(Run {
+                                first_grown: Some(share),
+                                ..run.clone()
+                            });
+                        }
+                    }
+                    continue;
+                }
+                (None, None) => {
+                    tasks[0].push(run);
+                    continue;
+                }
+            };
+            let weights = self.weights(&run, &classes, &words);
+            let total = weights.iter().fold(0, |a, &b| u128::saturating_add(a, b));
+            // each task starts at the first value that brings the candidates
+            // before it up to its share, rounded up
+            let n = n as u128;
+            let mut bounds = vec![lower];
+            let (mut m, mut sum) = (lower, 0u128);
+            for i in 1..n {
+                let share = total / n * i + (total % n * i).div_ceil(n);
+                while m < upper && sum < share {
+                    sum = sum.saturating_add(weights[m - lower]);
+                    m += 1;
+                }
+                bounds.push(m);
+            }
+            bounds.push(upper);
+            for (i, bounds) in bounds.windows(2).enumerate() {
+                let mut run = run.clone();
+                run.changes[0].lower = bounds[0];
+                run.changes[0].upper = bounds[1];
+                tasks[i].push(run);
+            }
+        }
+        tasks
+    }
+    /// Returns the number of a run's candidates that follow the policy and
+    /// fit the limits for each value of its first change, which is only
+    /// counted once for values that the rest of the run can't tell apart
+    fn weights(
+        &self,
+        run: &Run,
+        classes: &HashMap<usize, &[u8]>,
+        words: &HashMap<usize, (&[u8], &[usize])>,
+    ) -> Vec<u128> {
+        let first = &run.changes[0];
+        // values can be told apart by a pool or a later change that has to
+        // differ from them, and otherwise only by their width and the rules
+        // that their bytes count towards
+        let constrained = first.pool.is_some()
+            || run
+                .changes
+                .iter()
+                .any(|c| c.distinct.contains(&first.buf_idx));
+        let policy = self
+            .policy
+            .as_ref()
+            .filter(|policy| first.counts_towards(policy, classes, words));
+        let (members, offsets) = match first.values {
+            Values::Class(id) => (classes[&id], &[][..]),
+            Values::Words(id) => words[&id],
+            _ => (&[][..], &[][..]),
+        };
+        let most = run.shifts.first().filter(|shift| shift.change == 0);
+        let mut value = vec![0; first.width + most.map_or(0, |shift| shift.most)];
+        let mut counted = HashMap::new();
+        (first.lower..first.upper)
+            .map(|m| {
+                let width = first.write(m, members, offsets, &mut value);
+                let key = match policy {
+                    _ if constrained => (Some(m), vec![], 0),
+                    Some(policy) => (None, policy.effect(&value[..width]), width),
+                    None => (None, vec![], width),
+                };
+                *counted.entry(key).or_insert_with(|| {
+                    let mut run = run.clone();
+                    run.changes[0].lower = m;
+                    run.changes[0].upper = m + 1;
+                    self.total(&run, classes, words, false)
+                })
+            })
+            .collect()
+    }
+}
+
+/// Maps the ids of classes to their members
+fn by_id(dict: &Dict) -> HashMap<usize, &[u8]> {
+    dict.iter()
+        .map(|((class, _), &id)| (id, &class[..]))
+        .collect()
+}
+
+/// Maps the ids of words to their members and the offsets they start at
+fn words_by_id(words: &Words) -> HashMap<usize, (&[u8], &[usize])> {
+    words
+        .iter()
+        .map(|((members, offsets), &id)| (id, (&members[..], &offsets[..])))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+    fn runs(src: &str) -> Vec<Run> {
+        Final::new(
+            src,
+            &Options::default(),
+            &Limits::default(),
+            1,
+            Partition::None,
+        )
+        .unwrap()
+        .tasks
+        .remove(0)
+    }
+    #[test]
+    fn alternation() {
+        let runs = runs(r"x(ab|c\d{1,2})");
+        assert_eq!(
+            runs.iter().map(|r| r.len).collect::<Vec<_>>(),
+            vec![3, 3, 4]
+        );
+        assert_eq!(
+            runs[0].inits,
+            vec![Init::new(0, b'x'), Init::new(1, b'a'), Init::new(2, b'b')]
+        );
+        assert_eq!(runs[2].changes.len(), 2);
+    }
+    #[test]
+    fn group_quantifier() {
+        let runs = runs(r"(\d{2}-){3}\d{2}");
+        assert_eq!(runs.len(), 1);
+        assert_eq!(runs[0].len, 11);
+        assert_eq!(
+            runs[0].inits,
+            vec![Init::new(2, b'-'), Init::new(5, b'-'), Init::new(8, b'-')]
+        );
+        assert_eq!(
+            runs[0]
+                .changes
+                .iter()
+                .map(|c| c.buf_idx)
+                .collect::<Vec<_>>(),
+            vec![0, 1, 3, 4, 6, 7, 9, 10]
+        );
+    }
+    #[test]
+    fn group_range() {
+        let grouped = runs("(a|bb){0,2}c");
+        let lens: Vec<_> = grouped.iter().map(|r| r.len).collect();
+        assert_eq!(lens, vec![1, 2, 3, 3, 4, 4, 5]);
+        assert_eq!(
+            grouped[4].inits,
+            vec![
+                Init::new(0, b'a'),
+                Init::new(1, b'b'),
+                Init::new(2, b'b'),
+                Init::new(3, b'c')
+            ]
+        );
+        // alternatives of the same length are one change for each repeat
+        let merged = runs("(a|b){0,2}c");
+        let lens: Vec<_> = merged.iter().map(|r| r.len).collect();
+        assert_eq!(lens, vec![1, 2, 3]);
+        assert_eq!(
+            merged[2].changes,
+            vec![Change::new(0, 1, 2, 1), Change::new(1, 1, 2, 1)]
+        );
+    }
+    #[test]
+    fn wide_class() {
+        // members of different widths are one change that moves what's
+        // after it, not a run for each width
+        let runs = runs("x[aé]{2}");
+        assert_eq!(runs.len(), 1);
+        assert_eq!((runs[0].len, runs[0].max_len()), (3, 5));
+        let word = |idx| Change {
+            values: Values::Words(1),
+            ..Change::new(idx, 0, 2, 1)
+        };
+        assert_eq!(runs[0].changes, vec![word(1), word(2)]);
+        let shift = |buf_idx, change| Shift {
+            buf_idx,
+            change,
+            most: 1,
+        };
+        assert_eq!(runs[0].shifts, vec![shift(1, 0), shift(2, 1)]);
+        assert_eq!(
+            runs[0].writes(),
+            vec![
+                vec![Write::Init(0)],
+                vec![Write::Shift(0)],
+                vec![Write::Shift(1)]
+            ]
+        );
+    }
+    #[test]
+    fn wordlist() {
+        // words of different widths are one change, however they alternate
+        let dir = std::env::temp_dir().join(format!("bonk-runs-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let words: Vec<_> = (0..70_000)
+            .map(|i| format!("{:0width$}", i, width = 5 + i % 2))
+            .collect();
+        fs::write(dir.join("words.txt"), words.join("\n")).unwrap();
+        let opts = Options {
+            base_dir: dir.clone(),
+            ..Options::default()
+        };
+        let result = Final::new(
+            r"\f{words.txt}{2}",
+            &opts,
+            &Limits::default(),
+            1,
+            Partition::None,
+        );
+        fs::remove_dir_all(&dir).unwrap();
+        let result = result.unwrap();
+        assert_eq!(result.tasks[0].len(), 1);
+        assert_eq!(result.max_size, 12);
+        assert_eq!(result.keyspace, vec![70_000 * 70_000]);
+    }
+    #[test]
+    fn backreference() {
+        // a group that didn't take part captures nothing
+        let lens: Vec<_> = runs(r"(a){0,1}b\1").iter().map(|r| r.len).collect();
+        assert_eq!(lens, vec![1, 3]);
+        let runs = runs(r"(a|\d)-\1(é|[xé])\2\1");
+        assert_eq!(runs.len(), 4);
+        assert_eq!(
+            runs[0].inits,
+            vec![
+                Init::new(0, b'a'),
+                Init::new(1, b'-'),
+                Init::new(2, b'a'),
+                Init::new(3, 0xc3),
+                Init::new(4, 0xa9),
+                Init::new(5, 0xc3),
+                Init::new(6, 0xa9),
+                Init::new(7, b'a'),
+            ]
+        );
+        assert_eq!(runs[3].len, 6);
+        assert_eq!(
+            runs[3].changes,
+            vec![
+                Change {
+                    mirrors: vec![2, 5],
+                    ..Change::new(0, 1, 10, 1)
+                },
+                Change {
+                    values: Values::Words(1),
+                    mirrors: vec![4],
+                    ..Change::new(3, 0, 2, 1)
+                },
+            ]
+        );
+        // the copy moves what's after it like the original does, and the
+        // last copy of the digit waits for both of them
+        assert_eq!(
+            runs[3].writes(),
+            vec![
+                vec![Write::Init(0)],
+                vec![Write::Mirror {
+                    change: 0,
+                    buf_idx: 2
+                }],
+                vec![
+                    Write::Shift(0),
+                    Write::Shift(1),
+                    Write::Mirror {
+                        change: 0,
+                        buf_idx: 5
+                    },
+                    Write::Mirror {
+                        change: 1,
+                        buf_idx: 4
+                    }
+                ],
+            ]
+        );
+    }
+    #[test]
+    fn anagram() {
+        let runs = runs(r"\m{aab}x\m{cd}");
+        assert_eq!(runs[0].len, 6);
+        assert_eq!(runs[0].pools, vec![vec![2, 1], vec![1, 1]]);
+        assert_eq!(
+            runs[0]
+                .changes
+                .iter()
+                .map(|c| (c.buf_idx, c.pool))
+                .collect::<Vec<_>>(),
+            vec![
+                (0, Some(0)),
+                (1, Some(0)),
+                (2, Some(0)),
+                (4, Some(1)),
+                (5, Some(1))
+            ]
+        );
+    }
+    #[test]
+    fn distinct() {
+        let runs = runs(r"\d(?d:\a{2}-\d[aé]{2})\d");
+        assert_eq!(
+            runs[0]
+                .changes
+                .iter()
+                .map(|c| (c.buf_idx, c.distinct.clone()))
+                .collect::<Vec<_>>(),
+            vec![
+                (0, vec![]),
+                (1, vec![]),
+                (2, vec![1]),
+                (4, vec![1, 2]),
+                (5, vec![1, 2, 4]),
+                (6, vec![1, 2, 4, 5]),
+                (7, vec![]),
+            ]
+        );
+    }
+    #[test]
+    fn number() {
+        let runs = runs(r"\i{5..=12}(\i{0..=255:02x})-\1");
+        assert_eq!(runs.iter().map(|r| r.len).collect::<Vec<_>>(), vec![6, 7]);
+        assert_eq!(
+            runs[1].changes,
+            vec![
+                Change {
+                    values: Values::Number {
+                        start: 10,
+                        digits: b"0123456789",
+                        pad: b' ',
+                    },
+                    ..Change::new(0, 0, 3, 2)
+                },
+                Change {
+                    values: Values::Number {
+                        start: 0,
+                        digits: b"0123456789abcdef",
+                        pad: b'0',
+                    },
+                    mirrors: vec![5],
+                    ..Change::new(2, 0, 256, 2)
+                },
+            ]
+        );
+    }
+    #[test]
+    fn limits() {
+        let lens = |limits: &Limits| -> Vec<_> {
+            Final::new(
+                r"\a{1,3}\d{1,3}",
+                &Options::default(),
+                limits,
+                1,
+                Partition::None,
+            )
+            .unwrap()
+            .tasks[0]
+                .iter()
+                .map(|r| r.len)
+                .collect()
+        };
+        assert_eq!(lens(&Limits::default()), vec![2, 3, 4, 3, 4, 5, 4, 5, 6]);
+        let length = Some(4..=5);
+        assert_eq!(
+            lens(&Limits {
+                length: length.clone(),
+                ..Limits::default()
+            }),
+            vec![4, 4, 5, 4, 5]
+        );
+        assert_eq!(
+            lens(&Limits {
+                length,
+                shortest_first: true,
+                ..Limits::default()
+            }),
+            vec![4, 4, 4, 5, 5]
+        );
+        let final_ = Final::new(
+            r"\a{3}",
+            &Options::default(),
+            &Limits {
+                length: Some(1..=2),
+                ..Limits::default()
+            },
+            2,
+            Partition::Naive,
+        )
+        .unwrap();
+        assert_eq!(final_.tasks, vec![vec![], vec![]]);
+        assert_eq!(final_.max_size, 0);
+    }
+    #[test]
+    fn growth() {
+        let final_ = |src, length| {
+            Final::new(
+                src,
+                &Options::default(),
+                &Limits {
+                    length,
+                    ..Limits::default()
+                },
+                1,
+                Partition::None,
+            )
+            .unwrap()
+        };
+        let f = final_(r"(ab|c)\d{2,}", None);
+        let lens: Vec<_> = f.tasks[0].iter().map(|r| r.len).collect();
+        assert_eq!(lens, vec![4, 3]);
+        assert_eq!(
+            f.growth,
+            Some(Growth {
+                class_id: 1,
+                width: 1,
+                len: 10,
+                lengths: 0..=MAX_GROWN_LEN,
+            })
+        );
+        // only an upper bound on the length limit lifts the cap
+        let f = final_(r"x{0,}", Some(1..=usize::MAX));
+        assert_eq!(f.growth.map(|g| g.lengths), Some(1..=MAX_GROWN_LEN));
+        let f = final_(r"x{0,}", Some(1..=1000));
+        assert_eq!(f.growth.map(|g| g.lengths), Some(1..=1000));
+        let f = Final::new(
+            r"x{0,}",
+            &Options::default(),
+            &Limits {
+                max_grown_len: 20,
+                ..Limits::default()
+            },
+            1,
+            Partition::None,
+        )
+        .unwrap();
+        assert_eq!(f.growth.map(|g| g.lengths), Some(0..=20));
+        // runs that are too short can still grow into the limits
+        let f = final_(r"(ab|c)x{0,}", Some(4..=6));
+        let lens: Vec<_> = f.tasks[0].iter().map(|r| r.len).collect();
+        assert_eq!(lens, vec![2, 1]);
+        assert_eq!(f.growth.map(|g| (g.len, g.lengths)), Some((1, 4..=6)));
+        assert_eq!(final_(r"ab{1,}", Some(0..=1)).tasks[0], vec![]);
+    }
+    #[test]
+    fn keyspace() {
+        let keyspace = |src, limits: &Limits, num_threads| {
+            Final::new(
+                src,
+                &Options::default(),
+                limits,
+                num_threads,
+                Partition::Naive,
+            )
+            .unwrap()
+            .keyspace
+        };
+        let total = |src| keyspace(src, &Limits::default(), 1)[0];
+        assert_eq!(total(r"\a{1,2}\d"), 26 * 10 + 26 * 26 * 10);
+        assert_eq!(total(r"(?d:\a{3})"), 26 * 25 * 24);
+        // a \w that's a digit or lowercase letter leaves one fewer choice
+        assert_eq!(
+            total(r"(?d:\w\d\a)"),
+            10 * 9 * 26 + 26 * 10 * 25 + 27 * 10 * 26
+        );
+        assert_eq!(total(r"\m{aabc}"), 12);
+        assert_eq!(total(r"(?d:\m{ab}[a-c])"), 2);
+        assert_eq!(total(r"(\d)x\1"), 10);
+        assert_eq!(total(r"\b{17}"), u128::MAX);
+        for src in &[r"(?d:\a{3})", r"\m{aabbc}x", r"(?d:\m{abc}\a)\d"] {
+            let counts = keyspace(src, &Limits::default(), 3);
+            assert_eq!(counts.len(), 3);
+            assert_eq!(counts.iter().sum::<u128>(), total(src));
+        }
+        let policy = Limits {
+            policy: vec![Rule {
+                members: b"0123456789".to_vec(),
+                min: 1,
+                max: Some(1),
+            }],
+            ..Limits::default()
+        };
+        assert_eq!(keyspace(r"(\d)\d", &policy, 1), vec![0]);
+        assert_eq!(keyspace(r"\d[a\d]{2}", &policy, 1), vec![10]);
+        assert_eq!(
+            keyspace(r"(?d:\d[a-c1]{2})", &policy, 2)
+                .iter()
+                .sum::<u128>(),
+            60
+        );
+        // the shares hold about as many candidates as each other, even when
+        // many values of the first change don't follow the policy
+        for num_threads in 2..5 {
+            let counts = keyspace(r"\w\d", &policy, num_threads);
+            assert_eq!(counts.iter().sum::<u128>(), 53 * 10);
+            let share = 530 / num_threads as u128;
+            assert!(
+                counts.iter().all(|&c| c.abs_diff(share) <= 10),
+                "{:?}",
+                counts
+            );
+        }
+        let length = |length| Limits {
+            length: Some(length),
+            ..Limits::default()
+        };
+        assert_eq!(keyspace(r"a\d{1,}", &length(0..=3), 1), vec![110]);
+        assert_eq!(keyspace(r"ab{0,}", &length(3..=5), 1), vec![3]);
+        assert_eq!(
+            keyspace(r"a\d{0,}", &length(2..=usize::MAX), 1),
+            vec![u128::MAX]
+        );
+        // runs without changes are split by the first member that's added
+        for src in &[r"(c|de)\d{0,}", r"ab{1,}"] {
+            let counts = keyspace(src, &length(0..=4), 3);
+            assert_eq!(counts.len(), 3);
+            assert_eq!(
+                counts.iter().sum::<u128>(),
+                keyspace(src, &length(0..=4), 1)[0]
+            );
+        }
+        assert!(keyspace(r"c\d{0,}", &length(0..=4), 3)
+            .iter()
+            .all(|&n| n > 0));
+    }
+    #[test]
+    fn too_many_runs() {
+        for src in &[r"(a|b\d){0,15}", "(a|b){0,100}"] {
+            assert!(Final::new(
+                src,
+                &Options::default(),
+                &Limits::default(),
+                1,
+                Partition::None
+            )
+            .is_ok());
+        }
+        // the error points to where the pattern starts to have too many runs
+        for &(src, offset) in &[
+            ("a{0,65536}", 1),
+            (r"(a|b\d){0,16}", 7),
+            (r"((a|b\d){0,100}){0,100}", 16),
+            (r"x(a|b\d){0,15}(c|d\d)", 14),
+        ] {
+            assert_eq!(
+                Final::new(
+                    src,
+                    &Options::default(),
+                    &Limits::default(),
+                    1,
+                    Partition::None
+                )
+                .unwrap_err(),
+                ParseErr::new("pattern should expand to at most 65536 runs", offset)
+            );
+        }
+    }
+    #[test]
+    fn policy() {
+        let rule = |members: &[u8], min, max| Rule {
+            members: members.to_vec(),
+            min,
+            max,
+        };
+        let limits = Limits {
+            policy: vec![rule(b"ABC", 1, None), rule(b"0123456789", 1, Some(2))],
+            ..Limits::default()
+        };
+        let final_ = Final::new(
+            r"(\d{3}|[A-C0-9]{3}|x)[0AB]",
+            &Options::default(),
+            &limits,
+            1,
+            Partition::None,
+        )
+        .unwrap();
+        let policy = final_.policy.unwrap();
+        // each rule's count goes up to 1 and 3 respectively
+        assert_eq!(policy.len(), 8);
+        assert_eq!(policy.accept.iter().filter(|&&a| a).count(), 2);
+        // three digits are too many, and 'x' can't get a letter and a digit
+        let runs = &final_.tasks[0];
+        assert_eq!(runs.len(), 1);
+        let feasible = &runs[0].compliance.as_ref().unwrap().feasible;
+        assert_eq!(feasible.len(), 4);
+        // after the first three changes there are states with too many digits
+        assert!(feasible
+            .iter()
+            .all(|f| f.as_ref().unwrap().iter().any(|&f| !f)));
+        // numbers, integers, and wide classes count every byte they write
+        let limits = Limits {
+            policy: vec![rule(b"0123456789", 1, Some(1))],
+            ..Limits::default()
+        };
+        let keyspace = |src| {
+            Final::new(src, &Options::default(), &limits, 1, Partition::None)
+                .map(|final_| final_.keyspace[0])
+        };
+        assert_eq!(keyspace(r"\i{0..3}[a1]"), Ok(3));
+        // two-digit numbers have too many digits already
+        assert_eq!(keyspace(r"\i{8..12}[a1]"), Ok(2));
+        assert_eq!(keyspace(r"[é1]{2}"), Ok(2));
+        assert_eq!(keyspace(r"\u16le{48..50}x"), Ok(2));
+        assert_eq!(keyspace(r"(\i{0..3})\1"), Ok(0));
+        assert!(keyspace(r"\i{0..100000}").is_err());
+        // ranges that can't write a byte of a rule aren't counted
+        let limits = Limits {
+            policy: vec![rule(b"x", 1, None)],
+            ..Limits::default()
+        };
+        let final_ = Final::new(
+            r"\i{0..100000}x",
+            &Options::default(),
+            &limits,
+            1,
+            Partition::None,
+        )
+        .unwrap();
+        assert_eq!(final_.keyspace[0], 100_000);
+    }
+}
@@ -8,7 +8,7 @@ impl Bonk for Attacker {
     }
     fn check(&mut self, buf: &[u8]) -> bool {
         if buf == b"CTF{bru3e_f0rc3}" {
-            println!("{:?}", &buf);
+            println!("{:?}", buf);
             true
         } else {
             false
@@ -0,0 +1,430 @@
+//! Enumerates patterns that are compiled at runtime, for when the pattern
+//! isn't known until then and `bonk!` can't be used.
+
+use crate::odometer::{self, Slot};
+use crate::{Bonk, Deadline};
+use bonk_pattern::{Pattern, Run, Values, Write};
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
+/// Passes every candidate of the pattern to `B::check`, with the runs split
+/// across one thread per CPU if `threaded` is true, and returns true as soon
+/// as any call does; if the pattern has a timeout, it returns false once that
+/// passes without any call returning true
+pub fn run<B: Bonk>(pattern: &Pattern, threaded: bool) -> bool {
+    let flag = AtomicBool::new(false);
+    let deadline = pattern.timeout().map(Deadline::new);
+    if threaded {
+        let tasks = pattern.split(num_cpus::get());
+        thread::scope(|scope| {
+            for (thread_id, task) in tasks.iter().enumerate() {
+                let flag = &flag;
+                scope.spawn(move || self::task::<B>(pattern, task, thread_id, flag, deadline));
+            }
+        });
+    } else {
+        task::<B>(pattern, pattern.runs(), 0, &flag, deadline);
+    }
+    flag.load(Ordering::Relaxed)
+}
+
+/// Enumerates the runs of one thread, stopping once the flag is set or the
+/// deadline passes
+fn task<B: Bonk>(
+    pattern: &Pattern,
+    runs: &[Run],
+    thread_id: usize,
+    flag: &AtomicBool,
+    mut deadline: Option<Deadline>,
+) {
+    let mut bonker = B::new(thread_id);
+    let mut check = |candidate: &[u8]| {
+        if deadline.as_mut().is_some_and(Deadline::tick) {
+            return true;
+        }
+        if flag.load(Ordering::Relaxed) || bonker.check(candidate) {
+            flag.store(true, Ordering::Relaxed);
+            return true;
+        }
+        false
+    };
+    let mut buf = vec![0u8; pattern.max_len()];
+    let growth = match pattern.growth() {
+        Some(growth) => growth,
+        None => {
+            for run in runs {
+                if Walker::new(pattern, run, 0).walk(&mut buf, &mut check) {
+                    return;
+                }
+            }
+            return;
+        }
+    };
+    // an open-ended range makes every run longer by one member at a time,
+    // until the shortest run is longer than the limit
+    let shortest = match runs.iter().map(|run| run.len).min() {
+        Some(shortest) => shortest,
+        None => return,
+    };
+    for extra in 0usize.. {
+        let grown = extra.saturating_mul(growth.width);
+        if shortest.saturating_add(grown) > *growth.lengths.end() {
+            return;
+        }
+        buf.resize(pattern.max_len() + grown, 0);
+        for run in runs {
+            // words make some candidates longer than others, so the ones
+            // that don't fit are skipped one at a time
+            if run.len + grown > *growth.lengths.end()
+                || run.max_len() + grown < *growth.lengths.start()
+            {
+                continue;
+            }
+            // only one thread tries the run without any members added
+            if extra == 0
+                && run
+                    .first_grown
+                    .as_ref()
+                    .is_some_and(|first| first.start > 0)
+            {
+                continue;
+            }
+            if Walker::new(pattern, run, extra).walk(&mut buf, &mut check) {
+                return;
+            }
+        }
+    }
+}
+
+/// Enumerates one run, with the first change varying the slowest
+struct Walker<'a> {
+    pattern: &'a Pattern,
+    run: &'a Run,
+    /// The members that an open-ended range adds to the end of the run
+    extra: Vec<Slot<'a>>,
+    /// How many times each member of each pool has been drawn
+    used: Vec<Vec<usize>>,
+    /// What the run writes once each number of changes have taken their
+    /// values
+    writes: Vec<Vec<Write>>,
+    /// Where the value of each change is in the buffer
+    spans: Vec<Range<usize>>,
+    /// How far everything after each shift has moved
+    offsets: Vec<usize>,
+    /// The indexes of the earlier changes that each change has to differ from
+    distinct: Vec<Vec<usize>>,
+}
+
+impl<'a> Walker<'a> {
+    fn new(pattern: &'a Pattern, run: &'a Run, extra: usize) -> Self {
+        let extra = match pattern.growth() {
+            Some(growth) => (0..extra)
+                .map(|i| {
+                    let share = match &run.first_grown {
+                        Some(first) if i == 0 => first.clone(),
+                        _ => 0..growth.len,
+                    };
+                    Slot {
+                        buf_idx: run.len + i * growth.width,
+                        mirrors: &[],
+                        class: pattern.class(growth.class_id),
+                        width: growth.width,
+                        lower: share.start,
+                        upper: share.end,
+                    }
+                })
+                .collect(),
+            None => vec![],
+        };
+        let distinct = run
+            .changes
+            .iter()
+            .map(|change| {
+                change
+                    .distinct
+                    .iter()
+                    .map(|&d| {
+                        run.changes
+                            .iter()
+                            .position(|other| other.buf_idx == d)
+                            .expect("changes only differ from earlier changes")
+                    })
+                    .collect()
+            })
+            .collect();
+        Self {
+            pattern,
+            run,
+            extra,
+            used: run.pools.iter().map(|pool| vec![0; pool.len()]).collect(),
+            writes: run.writes(),
+            spans: vec![0..0; run.changes.len()],
+            offsets: vec![0; run.shifts.len()],
+            distinct,
+        }
+    }
+    /// Passes every candidate to `check`, returning true as soon as it does
+    fn walk(&mut self, buf: &mut [u8], check: &mut impl FnMut(&[u8]) -> bool) -> bool {
+        self.write(0, buf);
+        let start = self.run.compliance.as_ref().map_or(0, |c| c.start);
+        self.change(0, start, buf, check)
+    }
+    /// Returns where the words before `buf_idx` have moved whatever is there
+    fn at(&self, buf_idx: usize) -> usize {
+        buf_idx
+            + self
+                .run
+                .shift_before(buf_idx)
+                .map_or(0, |k| self.offsets[k])
+    }
+    /// Writes everything that waits for the first `depth` changes to take
+    /// their values
+    fn write(&mut self, depth: usize, buf: &mut [u8]) {
+        let run = self.run;
+        for i in 0..self.writes[depth].len() {
+            match self.writes[depth][i] {
+                Write::Shift(k) => {
+                    let change = run.shifts[k].change;
+                    let before = k.checked_sub(1).map_or(0, |k| self.offsets[k]);
+                    self.offsets[k] = before + self.spans[change].len() - run.changes[change].width;
+                }
+                Write::Init(i) => {
+                    let init = &run.inits[i];
+                    buf[self.at(init.buf_idx)] = init.val;
+                }
+                Write::Mirror { change, buf_idx } => {
+                    buf.copy_within(self.spans[change].clone(), self.at(buf_idx));
+                }
+            }
+        }
+    }
+    /// Tries every value of the change with index `j`, where `state` is the
+    /// policy's state after the changes before it
+    fn change(
+        &mut self,
+        j: usize,
+        state: usize,
+        buf: &mut [u8],
+        check: &mut impl FnMut(&[u8]) -> bool,
+    ) -> bool {
+        let run = self.run;
+        let change = match run.changes.get(j) {
+            Some(change) => change,
+            None => {
+                let len = run.len + self.offsets.last().copied().unwrap_or(0);
+                let grown = len + self.extra.iter().map(|slot| slot.width).sum::<usize>();
+                if run.lengths.as_ref().is_some_and(|l| !l.contains(&grown)) {
+                    return false;
+                }
+                if self.extra.is_empty() {
+                    return check(&buf[..len]);
+                }
+                let width = self.extra[0].width;
+                for (i, slot) in self.extra.iter_mut().enumerate() {
+                    slot.buf_idx = len + i * width;
+                }
+                let grown = odometer::Run {
+                    len: grown,
+                    inits: &[],
+                    slots: &self.extra,
+                };
+                return odometer::run(&grown, buf, check);
+            }
+        };
+        let start = self.at(change.buf_idx);
+        for m in change.lower..change.upper {
+            if let Some(k) = change.pool {
+                if self.used[k][m] == run.pools[k][m] {
+                    continue;
+                }
+            }
+            let (members, offsets) = match change.values {
+                Values::Class(id) => (self.pattern.class(id), &[][..]),
+                Values::Words(id) => self.pattern.words(id),
+                _ => (&[][..], &[][..]),
+            };
+            let end = start + change.write(m, members, offsets, &mut buf[start..]);
+            self.spans[j] = start..end;
+            if self.distinct[j]
+                .iter()
+                .any(|&d| buf[self.spans[d].clone()] == buf[start..end])
+            {
+                continue;
+            }
+            // backreferences count the bytes again
+            let mut next = state;
+            let feasible = run.compliance.as_ref().and_then(|c| c.feasible[j].as_ref());
+            if let (Some(policy), Some(feasible)) = (self.pattern.policy(), feasible) {
+                for _ in 0..=change.mirrors.len() {
+                    for &b in &buf[start..end] {
+                        next = policy.next[next][usize::from(policy.masks[usize::from(b)])];
+                    }
+                }
+                if !feasible[next] {
+                    continue;
+                }
+            }
+            self.write(j + 1, buf);
+            if let Some(k) = change.pool {
+                self.used[k][m] += 1;
+            }
+            let found = self.change(j + 1, next, buf, check);
+            if let Some(k) = change.pool {
+                self.used[k][m] -= 1;
+            }
+            if found {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bonk_pattern::{Limits, Options, Rule};
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    static SEEN: Mutex<Vec<Vec<u8>>> = Mutex::new(Vec::new());
+    static TRIED: Mutex<usize> = Mutex::new(0);
+
+    struct Collect;
+
+    impl Bonk for Collect {
+        fn new(_thread_id: usize) -> Self {
+            Self
+        }
+        fn check(&mut self, buf: &[u8]) -> bool {
+            SEEN.lock().unwrap().push(buf.to_vec());
+            false
+        }
+    }
+
+    struct Find;
+
+    impl Bonk for Find {
+        fn new(_thread_id: usize) -> Self {
+            Self
+        }
+        fn check(&mut self, buf: &[u8]) -> bool {
+            *TRIED.lock().unwrap() += 1;
+            buf == b"c90210"
+        }
+    }
+
+    struct Never;
+
+    impl Bonk for Never {
+        fn new(_thread_id: usize) -> Self {
+            Self
+        }
+        fn check(&mut self, _buf: &[u8]) -> bool {
+            false
+        }
+    }
+
+    fn candidates(pattern: &Pattern) -> Vec<String> {
+        assert!(!run::<Collect>(pattern, false));
+        let seen = std::mem::take(&mut *SEEN.lock().unwrap());
+        seen.into_iter()
+            .map(|c| String::from_utf8(c).unwrap())
+            .collect()
+    }
+    #[test]
+    fn order() {
+        let compile = |src| Pattern::compile(src).unwrap();
+        assert_eq!(
+            candidates(&compile(r"(a|bc)-\1\i{9..11:2}")),
+            ["a-a 9", "a-a10", "bc-bc 9", "bc-bc10"]
+        );
+        assert_eq!(candidates(&compile(r"\m{aab}")), ["aab", "aba", "baa"]);
+        assert_eq!(
+            candidates(&compile(r"[ab]-[ba]")),
+            ["a-b", "a-a", "b-b", "b-a"]
+        );
+        assert_eq!(
+            candidates(&compile(r"[ba]\m{aab}")),
+            ["baab", "baba", "bbaa", "aaab", "aaba", "abaa"]
+        );
+        assert_eq!(candidates(&compile(r"(?d:[ab]{2}[abc])")), ["abc", "bac"]);
+        // words move everything after them, backreferences included
+        assert_eq!(
+            candidates(&compile(r"([aé])\1[bü]!")),
+            ["aab!", "aaü!", "ééb!", "ééü!"]
+        );
+        assert_eq!(candidates(&compile(r"(?d:[aé]{2})")), ["aé", "éa"]);
+        let limits = Limits {
+            length: Some(3..=3),
+            ..Limits::default()
+        };
+        let pattern = Pattern::with_options(r"[aé]{2}", &Options::default(), &limits).unwrap();
+        assert_eq!(pattern.keyspace(), 2);
+        assert_eq!(candidates(&pattern), ["aé", "éa"]);
+        let limits = Limits {
+            length: Some(0..=2),
+            ..Limits::default()
+        };
+        let pattern = Pattern::with_options(r"[ab]{1,}", &Options::default(), &limits).unwrap();
+        assert_eq!(candidates(&pattern), ["a", "b", "aa", "ab", "ba", "bb"]);
+        let limits = Limits {
+            policy: vec![Rule {
+                members: b"a".to_vec(),
+                min: 1,
+                max: Some(1),
+            }],
+            ..Limits::default()
+        };
+        let pattern = Pattern::with_options(r"[ab]{2}", &Options::default(), &limits).unwrap();
+        assert_eq!(candidates(&pattern), ["ab", "ba"]);
+        let limits = Limits {
+            policy: vec![Rule {
+                members: b"0123456789".to_vec(),
+                min: 1,
+                max: Some(1),
+            }],
+            ..Limits::default()
+        };
+        let pattern = Pattern::with_options(r"\i{0..3}[a1]", &Options::default(), &limits).unwrap();
+        assert_eq!(candidates(&pattern), ["0a", "1a", "2a"]);
+        // runs without changes are split by the first member that's added
+        let limits = Limits {
+            length: Some(0..=3),
+            ..Limits::default()
+        };
+        let pattern = Pattern::with_options(r"c\d{0,}", &Options::default(), &limits).unwrap();
+        let flag = AtomicBool::new(false);
+        for (thread_id, runs) in pattern.split(3).iter().enumerate() {
+            task::<Collect>(&pattern, runs, thread_id, &flag, None);
+        }
+        let mut seen = std::mem::take(&mut *SEEN.lock().unwrap());
+        seen.sort();
+        let mut expected: Vec<_> = candidates(&pattern)
+            .into_iter()
+            .map(String::into_bytes)
+            .collect();
+        expected.sort();
+        assert_eq!(seen.len(), 111);
+        assert_eq!(seen, expected);
+    }
+    #[test]
+    fn threads() {
+        let pattern = Pattern::compile(r"c\d{0,}").unwrap();
+        assert!(run::<Find>(&pattern, true));
+        assert!(run::<Find>(&pattern, false));
+        let pattern = Pattern::compile(r"c\d{2}").unwrap();
+        *TRIED.lock().unwrap() = 0;
+        assert!(!run::<Find>(&pattern, true));
+        assert_eq!(*TRIED.lock().unwrap(), 100);
+        // a timeout stops patterns that would take far too long otherwise
+        let limits = Limits {
+            timeout: Some(Duration::from_millis(50)),
+            ..Limits::default()
+        };
+        let pattern = Pattern::with_options(r"\w{0,}", &Options::default(), &limits).unwrap();
+        assert!(!run::<Never>(&pattern, true));
+        assert!(!run::<Never>(&pattern, false));
+    }
+}
@@ -1,6 +1,50 @@
 pub use bonk_macro::bonk;
+pub use bonk_pattern::{self as pattern, Pattern};
+
+pub mod executor;
+pub mod odometer;
+
+use std::time::{Duration, Instant};
 
 pub trait Bonk {
     fn new(thread_id: usize) -> Self;
     fn check(&mut self, buf: &[u8]) -> bool;
 }
+
+/// How many candidates are checked between looks at the clock, which is slow
+/// next to checking a candidate
+const CLOCK_EVERY: usize = 1 << 12;
+
+/// When enumerating has to stop, for patterns with a timeout; each thread
+/// keeps its own copy
+#[derive(Clone, Copy, Debug)]
+pub struct Deadline {
+    at: Instant,
+    checked: usize,
+    passed: bool,
+}
+
+impl Deadline {
+    /// Starts a deadline that passes `timeout` from now
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            at: Instant::now() + timeout,
+            checked: 0,
+            passed: false,
+        }
+    }
+    /// Counts a candidate, and returns whether the deadline has passed as of
+    /// the last look at the clock
+    pub fn tick(&mut self) -> bool {
+        self.checked += 1;
+        if self.checked.is_multiple_of(CLOCK_EVERY) && Instant::now() >= self.at {
+            self.passed = true;
+        }
+        self.passed
+    }
+    /// Returns whether the deadline has passed as of the last look at the
+    /// clock
+    pub fn passed(&self) -> bool {
+        self.passed
+    }
+}
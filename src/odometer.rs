@@ -0,0 +1,127 @@
+//! A compact way of enumerating runs, which `bonk!` uses instead of nested
+//! loops when those would take too much code.
+
+/// A part of a candidate that takes every member of a class in turn
+pub struct Slot<'a> {
+    /// Where the slot's value is written
+    pub buf_idx: usize,
+    /// Other places that backreferences copy the slot's value to
+    pub mirrors: &'a [usize],
+    /// The members of the class, laid out back to back
+    pub class: &'a [u8],
+    /// The length of each member
+    pub width: usize,
+    /// The first member that the slot takes
+    pub lower: usize,
+    /// One past the last member that the slot takes
+    pub upper: usize,
+}
+
+impl Slot<'_> {
+    fn write(&self, buf: &mut [u8], member: usize) {
+        let value = &self.class[member * self.width..(member + 1) * self.width];
+        buf[self.buf_idx..self.buf_idx + self.width].copy_from_slice(value);
+        for &mirror in self.mirrors {
+            buf[mirror..mirror + self.width].copy_from_slice(value);
+        }
+    }
+}
+
+/// Candidates of the same length, made up of fixed bytes and slots
+pub struct Run<'a> {
+    pub len: usize,
+    /// The index and value of every byte that stays the same
+    pub inits: &'a [(usize, u8)],
+    pub slots: &'a [Slot<'a>],
+}
+
+/// Passes every candidate in the run to `check`, with the first slot
+/// changing the slowest, and returns true as soon as `check` does
+pub fn run(run: &Run<'_>, buf: &mut [u8], mut check: impl FnMut(&[u8]) -> bool) -> bool {
+    if run.slots.iter().any(|slot| slot.lower >= slot.upper) {
+        return false;
+    }
+    for &(buf_idx, val) in run.inits {
+        buf[buf_idx] = val;
+    }
+    let mut members: Vec<_> = run.slots.iter().map(|slot| slot.lower).collect();
+    for (slot, &member) in run.slots.iter().zip(&members) {
+        slot.write(buf, member);
+    }
+    loop {
+        if check(&buf[..run.len]) {
+            return true;
+        }
+        // like an odometer, the last slot turns over into the one before it
+        let mut i = run.slots.len();
+        loop {
+            if i == 0 {
+                return false;
+            }
+            i -= 1;
+            let slot = &run.slots[i];
+            members[i] += 1;
+            if members[i] == slot.upper {
+                members[i] = slot.lower;
+                slot.write(buf, members[i]);
+            } else {
+                slot.write(buf, members[i]);
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn order() {
+        let slot = |buf_idx, class, lower, upper| Slot {
+            buf_idx,
+            mirrors: &[],
+            class,
+            width: 1,
+            lower,
+            upper,
+        };
+        let slots = [slot(0, b"abc", 1, 3), slot(2, b"xy", 0, 2)];
+        let run = Run {
+            len: 3,
+            inits: &[(1, b'-')],
+            slots: &slots,
+        };
+        let mut seen = vec![];
+        let mut buf = [0; 3];
+        assert!(!super::run(&run, &mut buf, |c| {
+            seen.push(c.to_vec());
+            false
+        }));
+        assert_eq!(seen, vec![b"b-x", b"b-y", b"c-x", b"c-y"]);
+        assert!(super::run(&run, &mut buf, |c| c == b"c-x"));
+        let slots = [slot(0, b"ab", 0, 2), slot(1, b"", 0, 0)];
+        let run = Run {
+            slots: &slots,
+            ..run
+        };
+        assert!(!super::run(&run, &mut buf, |_| true));
+    }
+    #[test]
+    fn mirrors() {
+        let slots = [Slot {
+            buf_idx: 0,
+            mirrors: &[4],
+            class: "éa".as_bytes(),
+            width: 2,
+            lower: 0,
+            upper: 1,
+        }];
+        let run = Run {
+            len: 6,
+            inits: &[(2, b'+'), (3, b'+')],
+            slots: &slots,
+        };
+        let mut buf = [0; 6];
+        assert!(super::run(&run, &mut buf, |c| c == "é++é".as_bytes()));
+    }
+}
@@ -0,0 +1,51 @@
+use bonk::{bonk, Bonk};
+use std::sync::Mutex;
+
+static SEEN: Mutex<Vec<(usize, Vec<u8>)>> = Mutex::new(Vec::new());
+
+struct Collect {
+    thread_id: usize,
+}
+
+impl Bonk for Collect {
+    fn new(thread_id: usize) -> Self {
+        Self { thread_id }
+    }
+    fn check(&mut self, buf: &[u8]) -> bool {
+        SEEN.lock().unwrap().push((self.thread_id, buf.to_vec()));
+        false
+    }
+}
+
+/// Returns the candidates seen so far, grouped by thread but otherwise in the
+/// order that each thread saw them
+fn take() -> Vec<(usize, Vec<u8>)> {
+    let mut seen = std::mem::take(&mut *SEEN.lock().unwrap());
+    seen.sort_by_key(|&(thread_id, _)| thread_id);
+    seen
+}
+
+/// Returns what one expansion of `bonk!` tries, each in its own block so
+/// that the statics of different expansions don't clash
+macro_rules! candidates {
+    ($threaded:literal, $backend:ident) => {{
+        bonk!(
+            r"x(\a[éü])-\1[0-2]",
+            Collect,
+            false,
+            $threaded,
+            backend = $backend
+        );
+        take()
+    }};
+}
+
+#[test]
+fn same_candidates() {
+    let odometer = candidates!(false, odometer);
+    assert_eq!(odometer.len(), 26 * 2 * 3);
+    assert_eq!(odometer, candidates!(false, unrolled));
+    let odometer = candidates!(true, odometer);
+    assert_eq!(odometer.len(), 26 * 2 * 3);
+    assert_eq!(odometer, candidates!(true, unrolled));
+}